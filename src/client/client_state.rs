@@ -1,17 +1,43 @@
-use lycan::shared::gamestate::{Gamestate, Player};
-use lycan::shared::http::UpdateResponse;
-use lycan::shared::room::{Item, Room};
-use std::collections::HashMap;
+use lycan::shared::effects::Effect;
+use lycan::shared::game_settings::GameSettings;
+use lycan::shared::gamestate::{Gamestate, Map, Player, PlayerState, Role};
+use lycan::shared::http::{RejoinGameResponse, UpdateRequest, UpdateResponse};
+use lycan::shared::room::{Door, Item, Room, TileType};
+use lycan::shared::utils::{distance, room_coord, tile_coord, Direction, GIVE_DISTANCE};
+use std::collections::{HashMap, HashSet};
 
 pub struct ClientGamestate {
     pub gamestate: Gamestate,
     pub player_id: Option<String>,
+    /// Proves we are `player_id`, only ever sent to the server.
+    pub token: String,
     pub game_id: Option<String>,
     pub new_rooms: Vec<(i32, i32)>,
     pub cleared_rooms: Vec<(i32, i32)>,
     pub explored_rooms: HashMap<(i32, i32), bool>,
     pub rotation: f32,
     pub end: bool,
+    pub sequence: u64,
+    pub role: Role,
+    pub revealed_rooms: HashSet<(i32, i32)>,
+    pub fake_exit: Option<(i32, i32)>,
+    pub unlock: Option<((i32, i32), Direction)>,
+    pub drop_key: bool,
+    pub give_key: Option<String>,
+    pub selected_item: usize,
+    pub use_item: Option<(usize, Option<String>)>,
+    pub give_item: Option<(usize, String)>,
+    pub effects: Vec<Effect>,
+    pub marker: bool,
+    pub markers: Vec<(f32, f32)>,
+    pub countdown: Option<u64>,
+    pub host: Option<String>,
+    pub settings: Option<GameSettings>,
+    pub kick: Option<String>,
+    /// The server says the keys found open the exit, `gamestate.keys` counts disguised curses too.
+    pub exit_open: bool,
+    /// The server closed our connection, we were kicked or the game is gone.
+    pub disconnected: bool,
 }
 
 impl ClientGamestate {
@@ -20,11 +46,31 @@ impl ClientGamestate {
             gamestate: Gamestate::default(),
             new_rooms: Vec::new(),
             player_id: None,
+            token: String::new(),
             game_id: None,
             explored_rooms: HashMap::new(),
             cleared_rooms: Vec::new(),
             rotation: 0.,
             end: false,
+            sequence: 0,
+            role: Role::Explorer,
+            revealed_rooms: HashSet::new(),
+            fake_exit: None,
+            unlock: None,
+            drop_key: false,
+            give_key: None,
+            selected_item: 0,
+            use_item: None,
+            give_item: None,
+            effects: Vec::new(),
+            marker: false,
+            markers: Vec::new(),
+            countdown: None,
+            host: None,
+            settings: None,
+            kick: None,
+            exit_open: false,
+            disconnected: false,
         }
     }
 
@@ -39,6 +85,35 @@ impl ClientGamestate {
         }
     }
 
+    /// Whether a room should be drawn, the traitor also sees what everyone else explored.
+    pub fn visible(&self, position: (i32, i32)) -> bool {
+        self.explored(position) || self.revealed_rooms.contains(&position)
+    }
+
+    /// Whole seconds left before the round starts, while counting down.
+    pub fn countdown_seconds(&self) -> Option<u64> {
+        self.countdown.map(|countdown| (countdown + 999) / 1000)
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.host.is_some() && self.host == self.player_id
+    }
+
+    /// Everyone in the game sorted by name, the way the lobby lists them.
+    pub fn lobby_players(&self) -> Vec<(&String, &Player)> {
+        let mut players: Vec<(&String, &Player)> = self.gamestate.players.iter().collect();
+        players.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+        players
+    }
+
+    pub fn is_traitor(&self) -> bool {
+        self.role == Role::Traitor
+    }
+
+    pub fn is_ghost(&self) -> bool {
+        self.get_player().map_or(false, |player| player.state == PlayerState::Ghost)
+    }
+
     pub fn get_player(&self) -> Option<&Player> {
         match &self.player_id {
             Some(player_id) => Some(self.gamestate.players.get(player_id)?),
@@ -53,16 +128,30 @@ impl ClientGamestate {
         }
     }
 
-    pub fn set_player(&mut self, player_id: String, position: (f32, f32)) {
+    pub fn set_player(&mut self, player_id: String, token: String, position: (f32, f32)) {
         self.gamestate.players.insert(
             player_id.clone(),
             Player {
                 name: String::from("foo"),
                 position,
                 ready: false,
+                keys: 0,
+                inventory: vec![],
+                state: PlayerState::Alive,
+                color: (255, 255, 255),
             },
         );
         self.player_id = Some(player_id);
+        self.token = token;
+    }
+
+    pub fn rejoin(&mut self, game_id: String, player_id: String, token: String, data: RejoinGameResponse) {
+        self.set_game(game_id);
+        self.set_player(player_id, token, data.position);
+        for room in data.explored_rooms {
+            self.explored_rooms.insert(room, true);
+        }
+        self.update(data.state);
     }
 
     pub fn get_players(&self) -> &HashMap<String, Player> {
@@ -74,10 +163,7 @@ impl ClientGamestate {
     }
 
     pub fn player_room_coord(&self) -> (i32, i32) {
-        let position = self.player_position();
-        let room_x = position.0 / 16. / 16.;
-        let room_y = position.1 / 16. / 16.;
-        (room_x.floor() as i32, room_y.floor() as i32)
+        room_coord(self.player_position())
     }
 
     pub fn player_position(&self) -> (f32, f32) {
@@ -85,11 +171,7 @@ impl ClientGamestate {
     }
 
     pub fn player_tile(&self) -> (i32, i32) {
-        let position = self.get_player().unwrap().position;
-        (
-            (((position.0 as i32).rem_euclid(16 * 16)) as f32 / 16.0).floor() as i32,
-            (((position.1 as i32).rem_euclid(16 * 16)) as f32 / 16.0).floor() as i32,
-        )
+        tile_coord(self.player_position())
     }
 
     pub fn player_room(&self) -> Option<&Room> {
@@ -107,10 +189,71 @@ impl ClientGamestate {
             .mut_room(player_room_coord.0, player_room_coord.1)
     }
 
-    pub fn player_in_wall(&self) -> bool {
+    pub fn player_blocked(&self, movement: (f32, f32)) -> bool {
         let tile = self.player_tile();
         let room = self.player_room().unwrap();
-        room.is_wall(tile)
+        room.blocks(tile, movement)
+    }
+
+    /// The locked door the player is standing against, if any.
+    pub fn player_locked_door(&self) -> Option<Direction> {
+        let room = self.player_room()?;
+        match room.tile(self.player_tile()).tile_type {
+            TileType::Door(direction) if room.doors.get(&direction) == Door::Locked => Some(direction),
+            _ => None,
+        }
+    }
+
+    /// Closest other player near enough to hand a key or an item to.
+    pub fn nearest_teammate(&self) -> Option<String> {
+        let position = self.player_position();
+        let distance = |player: &Player| distance(player.position, position);
+        self.gamestate
+            .players
+            .iter()
+            .filter(|(id, player)| Some(*id) != self.player_id.as_ref() && distance(player) <= GIVE_DISTANCE)
+            .min_by(|(_, a), (_, b)| distance(a).partial_cmp(&distance(b)).unwrap())
+            .map(|(id, _)| id.clone())
+    }
+
+    pub fn inventory(&self) -> &[Item] {
+        match self.get_player() {
+            Some(player) => &player.inventory,
+            None => &[],
+        }
+    }
+
+    /// Uses the selected item on the nearest teammate, or on ourselves if `on_teammate` is false.
+    pub fn use_selected_item(&mut self, on_teammate: bool) {
+        if self.selected_item >= self.inventory().len() {
+            return;
+        }
+        let target = if on_teammate {
+            match self.nearest_teammate() {
+                Some(teammate) => Some(teammate),
+                None => return,
+            }
+        } else {
+            None
+        };
+        self.use_item = Some((self.selected_item, target));
+    }
+
+    pub fn give_selected_item(&mut self) {
+        if self.selected_item >= self.inventory().len() {
+            return;
+        }
+        if let Some(teammate) = self.nearest_teammate() {
+            self.give_item = Some((self.selected_item, teammate));
+        }
+    }
+
+    pub fn stacks(&self, item: &Item) -> u32 {
+        self.effects
+            .iter()
+            .filter(|effect| &effect.item == item)
+            .map(|effect| effect.stacks)
+            .sum()
     }
 
     pub fn player_in_door(&self) -> bool {
@@ -137,32 +280,20 @@ impl ClientGamestate {
     }
 
     pub fn remove_item(&mut self) {
-        let mut room = self.mut_player_room().unwrap();
-        let item = room.item.clone();
+        let room = self.mut_player_room().unwrap();
         room.item = None;
-        if let Some((item, _)) = item {
-            match item {
-                Item::Bad => {}
-                Item::Key => {}
-                Item::Spin => {
-                    self.rotation += 30.;
-                }
-                Item::Clear => {
-                    self.explored_rooms.clear();
-                }
-            }
-        }
         self.cleared_rooms.push(self.player_room_coord());
     }
 
     pub fn add_room(&mut self, position: (i32, i32)) {
+        if !self.explored(position) {
+            self.new_rooms.push(position);
+        }
         *self.explored_rooms.entry(position.clone()).or_insert(true) = true;
         if !self.gamestate.map.room(position.0, position.1).is_none() {
             return;
         }
-        self.new_rooms.push(position);
         self.gamestate.add_room(position);
-        self.gamestate.remove_doors(position);
     }
 
     pub fn add_player_room(&mut self) {
@@ -206,26 +337,102 @@ impl ClientGamestate {
         }
     }
 
+    pub fn update_request(&mut self) -> Option<UpdateRequest> {
+        let player = self.get_player()?;
+        let (position, ready) = (player.position, player.ready);
+        Some(UpdateRequest {
+            game_id: self.get_game_id(),
+            player_id: self.get_player_id(),
+            token: self.token.clone(),
+            position,
+            new_rooms: self.get_new_rooms(),
+            cleared_rooms: self.get_cleared_rooms(),
+            ready,
+            end: self.end,
+            fake_exit: self.fake_exit.take(),
+            unlock: self.unlock.take(),
+            drop_key: std::mem::replace(&mut self.drop_key, false),
+            give_key: self.give_key.take(),
+            use_item: self.use_item.take(),
+            give_item: self.give_item.take(),
+            marker: std::mem::replace(&mut self.marker, false),
+            settings: self.settings.take(),
+            kick: self.kick.take(),
+            ack: self.sequence,
+        })
+    }
+
     pub fn update(&mut self, data: UpdateResponse) {
+        if !data.snapshot && data.since > self.sequence {
+            // We missed some changes, ask the server for a new snapshot
+            self.sequence = 0;
+            return;
+        }
         if data.round > self.gamestate.round {
             self.end = false;
             let player_id = self.player_id.as_ref().unwrap();
-            self.gamestate.players.get_mut(player_id).unwrap().position =
-                data.players.get(player_id).unwrap().position;
+            if let Some(player) = data.players.get(player_id) {
+                self.gamestate.players.get_mut(player_id).unwrap().position = player.position;
+            }
             self.new_rooms.push(self.player_room_coord());
             self.gamestate.round = data.round;
         }
-        self.gamestate.map = data.map;
+        if data.snapshot {
+            self.gamestate.map = Map::from_rooms(data.rooms);
+            self.revealed_rooms = data.revealed_rooms.into_iter().collect();
+            // We explored what the server says we did, a clear curse or a new round wipes it
+            let (map, new_rooms) = (&self.gamestate.map, &self.new_rooms);
+            self.explored_rooms
+                .retain(|room, _| map.room(room.0, room.1).is_some() || new_rooms.contains(room));
+            // The server may not know yet about the room we just walked into
+            if !self.is_ghost() {
+                let position = self.player_room_coord();
+                self.add_room(position);
+            }
+            let player_id = self.player_id.clone();
+            let players = &data.players;
+            self.gamestate
+                .players
+                .retain(|id, _| players.contains_key(id) || Some(id) == player_id.as_ref());
+        } else {
+            for room in data.rooms {
+                self.gamestate.map.add_room(room.position, room);
+            }
+            self.revealed_rooms.extend(data.revealed_rooms);
+        }
+        for player_id in data.hidden_players {
+            if Some(&player_id) != self.player_id.as_ref() {
+                self.gamestate.players.remove(&player_id);
+            }
+        }
+        self.role = data.role;
         self.gamestate.started = data.started;
+        self.gamestate.phase = data.phase;
+        self.countdown = data.countdown;
+        self.gamestate.settings = data.settings;
+        self.host = data.host;
+        self.gamestate.results = data.results;
         self.gamestate.keys = data.keys;
-        self.gamestate.messages = data.messages;
+        self.exit_open = data.exit_open;
+        self.gamestate.messages.truncate(data.messages_from);
+        self.gamestate.messages.extend(data.messages);
         for (player_id, player_state) in data.players {
             if self.gamestate.started && player_id == self.player_id.as_ref().unwrap().clone() {
+                let player = self.get_mut_player().unwrap();
+                player.keys = player_state.keys;
+                player.inventory = player_state.inventory;
+                player.state = player_state.state;
+                player.name = player_state.name;
+                player.color = player_state.color;
                 continue;
             }
             match self.gamestate.players.get_mut(&player_id) {
                 Some(player) => {
                     player.position = player_state.position;
+                    player.keys = player_state.keys;
+                    player.state = player_state.state;
+                    player.name = player_state.name;
+                    player.color = player_state.color;
                 }
                 None => {
                     self.gamestate.players.insert(
@@ -234,10 +441,22 @@ impl ClientGamestate {
                             position: player_state.position,
                             name: player_state.name,
                             ready: false,
+                            keys: player_state.keys,
+                            inventory: player_state.inventory,
+                            state: player_state.state,
+                            color: player_state.color,
                         },
                     );
                 }
             }
         }
+        self.effects = data.effects;
+        self.markers = data.markers;
+        self.rotation = self.stacks(&Item::Spin) as f32 * 30.;
+        if let Some(position) = data.correction {
+            self.end = false;
+            self.get_mut_player().unwrap().position = position;
+        }
+        self.sequence = data.sequence;
     }
 }
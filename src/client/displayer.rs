@@ -1,7 +1,7 @@
 use crate::client_state::ClientGamestate;
-use lycan::shared::gamestate::Player;
-use lycan::shared::room::{Item, Room, Tile, TileType, WallType};
-use lycan::shared::utils::Direction;
+use lycan::shared::gamestate::{Phase, Player, PlayerState, Role};
+use lycan::shared::room::{Door, Item, Room, Tile, TileType, WallType};
+use lycan::shared::utils::{room_coord, Direction};
 use sfml::{
     graphics::{
         CircleShape, Color, Font, IntRect, RectangleShape, RenderTarget, RenderWindow, Shape,
@@ -105,23 +105,116 @@ impl Displayer {
             .set_rotation(gamestate.read().unwrap().rotation);
         window.set_view(&self.game_view);
         for room in gamestate.read().unwrap().get_rooms() {
-            if gamestate.read().unwrap().explored(room.position) {
+            if gamestate.read().unwrap().visible(room.position) {
                 self.draw_room(window, &room);
             }
         }
+        for position in &gamestate.read().unwrap().markers {
+            let mut marker = CircleShape::new(6.0, 4);
+            marker.set_origin((6.0, 6.0));
+            marker.set_position(*position);
+            marker.set_fill_color(Color::TRANSPARENT);
+            marker.set_outline_color(Color::rgba(200, 200, 255, 200));
+            marker.set_outline_thickness(2.);
+            window.draw(&marker);
+        }
+        let rotation = gamestate.read().unwrap().rotation;
         for (id, player) in gamestate.read().unwrap().get_players() {
+            let local = gamestate.read().unwrap().player_id.as_ref() == Some(id);
+            // Only the server decides who we see, but don't draw anyone standing in the dark
+            if !local && !gamestate.read().unwrap().visible(room_coord(player.position)) {
+                continue;
+            }
+            let (r, g, b) = player.color;
+            let alpha = if player.state == PlayerState::Ghost { 100 } else { 255 };
             let mut player_sprite = CircleShape::new(4.0, 100);
             player_sprite.set_origin((4.0, 4.0));
             player_sprite.set_position(player.position);
+            player_sprite.set_fill_color(Color::rgba(r, g, b, alpha));
+            if local {
+                player_sprite.set_outline_color(Color::WHITE);
+                player_sprite.set_outline_thickness(1.5);
+            }
             window.draw(&player_sprite);
+
+            let mut name = Text::default();
+            name.set_font(&self.font);
+            name.set_string(&player.name);
+            name.set_character_size(10);
+            name.set_fill_color(if local { Color::WHITE } else { Color::rgba(r, g, b, alpha) });
+            let bounds = name.local_bounds();
+            // Turned with the view so the name stays upright above the player
+            name.set_origin((bounds.left + bounds.width / 2., bounds.top + bounds.height + 8.));
+            name.set_position(player.position);
+            name.set_rotation(rotation);
+            window.draw(&name);
         }
         window.set_view(&self.hud_view);
         self.display_hud(window, &gamestate);
+        if gamestate.read().unwrap().gamestate.phase == Phase::Summary {
+            self.display_summary(window, &gamestate);
+        }
+        if let Some(seconds) = gamestate.read().unwrap().countdown_seconds() {
+            self.display_countdown(window, seconds);
+        }
         window.display();
     }
 
+    fn display_countdown(&mut self, window: &mut RenderWindow, seconds: u64) {
+        let mut text = Text::default();
+        text.set_font(&self.font);
+        text.set_string(&seconds.to_string());
+        text.set_character_size(120);
+        text.set_fill_color(Color::WHITE);
+        text.set_outline_color(Color::BLACK);
+        text.set_outline_thickness(4.);
+        let bounds = text.local_bounds();
+        text.set_origin((bounds.left + bounds.width / 2., bounds.top + bounds.height / 2.));
+        text.set_position((self.size.x as f32 / 2., self.size.y as f32 / 2.));
+        window.draw(&text);
+    }
+
+    /// How the last round ended and how far the explorers are from winning the match.
+    fn display_summary(&mut self, window: &mut RenderWindow, gamestate: &Arc<RwLock<ClientGamestate>>) {
+        let gamestate = gamestate.read().unwrap();
+        let result = match gamestate.gamestate.results.last() {
+            Some(result) => result,
+            None => return,
+        };
+        let exits = gamestate
+            .gamestate
+            .results
+            .iter()
+            .filter(|result| result.winner == Role::Explorer)
+            .count();
+        let lines = [
+            format!("Round {} is over", result.round),
+            match result.winner {
+                Role::Explorer => format!("{} found the exit", result.player),
+                Role::Traitor => format!("{} took a fake exit", result.player),
+            },
+            format!("Exits found: {}/{}", exits, gamestate.gamestate.settings.rounds),
+        ];
+        let mut background = RectangleShape::new();
+        background.set_size((self.size.x as f32, 200.));
+        background.set_position((0., self.size.y as f32 / 2. - 100.));
+        background.set_fill_color(Color::rgba(0, 0, 0, 200));
+        window.draw(&background);
+        for (index, line) in lines.iter().enumerate() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            text.set_string(line);
+            text.set_fill_color(Color::WHITE);
+            let bounds = text.local_bounds();
+            text.set_origin((bounds.width / 2., bounds.height / 2.));
+            text.set_position((self.size.x as f32 / 2., self.size.y as f32 / 2. - 50. + index as f32 * 50.));
+            window.draw(&text);
+        }
+    }
+
     fn display_hud(&mut self, window: &mut RenderWindow, gamestate: &Arc<RwLock<ClientGamestate>>) {
         let keys = gamestate.read().unwrap().gamestate.keys;
+        let keys_required = gamestate.read().unwrap().gamestate.settings.keys;
         for i in 0..keys {
             let mut rect = RectangleShape::new();
             rect.set_position((i as f32 * 20. + 40., 40.));
@@ -130,7 +223,7 @@ impl Displayer {
             rect.set_fill_color(Color::CYAN);
             window.draw(&rect);
         }
-        for i in keys..8 {
+        for i in keys..keys_required {
             let mut rect = RectangleShape::new();
             rect.set_position((i as f32 * 20. + 40., 40.));
             rect.set_outline_thickness(2.);
@@ -140,6 +233,54 @@ impl Displayer {
             rect.set_fill_color(Color::TRANSPARENT);
             window.draw(&rect);
         }
+        if let Some(player) = gamestate.read().unwrap().get_player() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            text.set_string(&format!("x{}", player.keys));
+            text.set_fill_color(Color::CYAN);
+            text.set_position((keys_required as f32 * 20. + 40., 22.));
+            window.draw(&text);
+        }
+        let selected = gamestate.read().unwrap().selected_item;
+        for (index, item) in gamestate.read().unwrap().inventory().iter().enumerate() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            text.set_string(&format!("{} {}", index + 1, item.to_string()));
+            text.set_fill_color(if index == selected { Color::YELLOW } else { Color::WHITE });
+            text.set_position((self.size.x as f32 - 200., 22. + index as f32 * 30.));
+            window.draw(&text);
+        }
+        for (index, effect) in gamestate.read().unwrap().effects.iter().enumerate() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            let mut label = effect.item.to_string();
+            if effect.stacks > 1 {
+                label = format!("{} x{}", label, effect.stacks);
+            }
+            if let Some(remaining) = effect.remaining {
+                label = format!("{} {}s", label, (remaining + 999) / 1000);
+            }
+            text.set_string(&label);
+            text.set_fill_color(Color::MAGENTA);
+            text.set_position((self.size.x as f32 - 400., 22. + index as f32 * 30.));
+            window.draw(&text);
+        }
+        if gamestate.read().unwrap().is_ghost() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            text.set_string("You are a ghost, press M to leave a marker");
+            text.set_fill_color(Color::rgb(200, 200, 255));
+            text.set_position((40., 70.));
+            window.draw(&text);
+        }
+        if gamestate.read().unwrap().is_traitor() {
+            let mut text = Text::default();
+            text.set_font(&self.font);
+            text.set_string("You are the traitor");
+            text.set_fill_color(Color::RED);
+            text.set_position((40., 70.));
+            window.draw(&text);
+        }
         for (index, message) in gamestate
             .read()
             .unwrap()
@@ -190,18 +331,26 @@ impl Displayer {
     fn draw_tile(&mut self, window: &mut RenderWindow, room: &Room, tile: Tile) {
         let mut sprite = match tile.tile_type {
             TileType::Floor => self.sprite(18, 18),
-            TileType::Door(direction) => {
-                if room.doors[&direction.to_string()] {
-                    self.sprite(18, 18)
-                } else {
-                    match direction {
+            TileType::Door(direction) => match room.doors.get(&direction) {
+                Door::Open => self.sprite(18, 18),
+                Door::OneWay => {
+                    let mut sprite = self.sprite(18, 18);
+                    sprite.set_color(Color::rgb(170, 170, 255));
+                    sprite
+                }
+                door => {
+                    let mut sprite = match direction {
                         Direction::Up => self.sprite(18, 52),
                         Direction::Down => self.sprite(18, 0),
                         Direction::Left => self.sprite(0, 18),
                         Direction::Right => self.sprite(35, 18),
+                    };
+                    if door == Door::Locked {
+                        sprite.set_color(Color::YELLOW);
                     }
+                    sprite
                 }
-            }
+            },
             TileType::Wall(wall_type) => match wall_type {
                 WallType::East => self.sprite(35, 18),
                 WallType::West => self.sprite(0, 18),
@@ -217,7 +366,20 @@ impl Displayer {
                 WallType::OuterSouthWest => self.sprite(69, 18),
                 _ => return,
             },
-            TileType::Exit => self.sprite(86, 103),
+            // A floor tile gone dark, so pits show up against the floor around them
+            TileType::Pit => {
+                let mut sprite = self.sprite(18, 18);
+                sprite.set_color(Color::rgb(25, 20, 20));
+                sprite
+            }
+            TileType::Exit => {
+                let mut sprite = self.sprite(86, 103);
+                // Only the traitor is told which exit is fake
+                if room.is_fake_exit() {
+                    sprite.set_color(Color::RED);
+                }
+                sprite
+            }
             _ => return,
         };
         sprite.set_position((
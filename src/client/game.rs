@@ -1,13 +1,11 @@
 use crate::client_state::ClientGamestate;
 use crate::displayer::Displayer;
-use crate::http;
-use lycan::shared::room::{TileType};
-use lycan::shared::utils::Direction;
+use lycan::shared::gamestate::Phase;
+use lycan::shared::room::{Item, TileType};
+use lycan::shared::utils::{Direction, UPDATE_INTERVAL};
 use sfml::{
-    graphics::{
-        CircleShape, Color, RenderTarget, RenderWindow, Shape, Sprite, Transformable,
-    },
-    system::Vector2,
+    graphics::{RenderTarget, RenderWindow, Sprite},
+    system::Clock,
     window::{Event, Key},
 };
 use std::{
@@ -16,9 +14,14 @@ use std::{
     time::Duration,
 };
 
+/// Update intervals a single frame can move the player for.
+const MAX_FRAME_STEPS: f32 = 4.;
+
 pub enum GameResult {
     Menu,
     Quit,
+    /// The match is over, time for the results.
+    End,
 }
 
 pub fn start_game(
@@ -31,63 +34,15 @@ pub fn start_game(
         gamestate.add_player_room();
     }
 
-    let thread_gamestate = Arc::clone(&gamestate);
-    thread::spawn(move || loop {
-        {
-            let game_id = thread_gamestate.read().unwrap().get_game_id().clone();
-            let player_id = thread_gamestate.read().unwrap().get_player_id().clone();
-            let position = thread_gamestate.read().unwrap().get_player().unwrap().position;
-            let ready = thread_gamestate.read().unwrap().get_player().unwrap().ready;
-            let new_rooms = thread_gamestate.write().unwrap().get_new_rooms();
-            let cleared_rooms = thread_gamestate.write().unwrap().get_cleared_rooms();
-            let end = thread_gamestate.write().unwrap().end;
-            match http::update(&game_id, &player_id, position, new_rooms, cleared_rooms, ready, end) {
-                Ok(data) => {
-                    thread_gamestate.write().unwrap().update(data);
-                }
-                Err(err) => println!("{}", err),
-            };
-        }
-        thread::sleep(Duration::from_millis(15));
-    });
-
     let mut displayer = Displayer::new(window.size());
 
-    while !gamestate.read().unwrap().is_started() {
-        while let Some(event) = window.poll_event() {
-            if !window.has_focus() {
-                continue
-            }
-            match event {
-                Event::KeyPressed {code: Key::Return, ..} =>  {
-                    let mut gamestate = gamestate.write().unwrap();
-                    let mut player = gamestate.get_mut_player().unwrap();
-                    player.ready = !player.ready;
-                    println!("{}", player.ready);
-                }
-                _ => {}
-            }
-        }
-        window.clear(Color::BLACK);
-        let mut circle = CircleShape::new(50., 100);
-        circle.set_origin((50., 50.));
-        circle.set_position(Vector2::from((window.size().x as f32/2., window.size().y as f32/2.)));
-        if gamestate.read().unwrap().get_player().unwrap().ready {
-            circle.set_fill_color(Color::GREEN);
-        } else {
-            circle.set_fill_color(Color::RED);
-        }
-        window.draw(&circle);
-        window.display();
-        thread::sleep(Duration::from_millis(15));
-    }
-
     {
         let gamestate = gamestate.read().unwrap();
         let position = gamestate.player_position();
         displayer.set_center(position);
     }
 
+    let mut clock = Clock::start();
     loop {
         while let Some(event) = window.poll_event() {
             if !window.has_focus() {
@@ -98,6 +53,38 @@ pub fn start_game(
                 | Event::KeyPressed {
                     code: Key::Escape, ..
                 } => return GameResult::Quit,
+                Event::KeyPressed { code: Key::G, .. } => {
+                    let mut gamestate = gamestate.write().unwrap();
+                    gamestate.drop_key = true;
+                }
+                Event::KeyPressed { code: Key::H, .. } => {
+                    let mut gamestate = gamestate.write().unwrap();
+                    gamestate.give_key = gamestate.nearest_teammate();
+                }
+                Event::KeyPressed { code: Key::E, .. } => {
+                    gamestate.write().unwrap().use_selected_item(false);
+                }
+                Event::KeyPressed { code: Key::R, .. } => {
+                    gamestate.write().unwrap().use_selected_item(true);
+                }
+                Event::KeyPressed { code: Key::T, .. } => {
+                    gamestate.write().unwrap().give_selected_item();
+                }
+                Event::KeyPressed { code, .. } if code >= Key::Num1 && code <= Key::Num9 => {
+                    gamestate.write().unwrap().selected_item = code as usize - Key::Num1 as usize;
+                }
+                Event::KeyPressed { code: Key::M, .. } => {
+                    let mut gamestate = gamestate.write().unwrap();
+                    if gamestate.is_ghost() {
+                        gamestate.marker = true;
+                    }
+                }
+                Event::KeyPressed { code: Key::F, .. } => {
+                    let mut gamestate = gamestate.write().unwrap();
+                    if gamestate.is_traitor() {
+                        gamestate.fake_exit = Some(gamestate.player_room_coord());
+                    }
+                }
                 Event::MouseMoved { x, y } => {
                     // let gamestate = Arc::clone(&gamestate);
                     // let mut gamestate = gamestate.write().unwrap();
@@ -107,23 +94,30 @@ pub fn start_game(
             }
         }
 
-        if Key::Q.is_pressed() {
+        if Key::Q.is_pressed() || gamestate.read().unwrap().disconnected {
             return GameResult::Menu;
         }
+        if gamestate.read().unwrap().gamestate.phase == Phase::End {
+            return GameResult::End;
+        }
 
+        // Speed is per update interval, whatever the frame rate, and a long stall doesn't turn
+        // into a jump the server would reject
+        let frame = clock.restart().as_milliseconds() as f32 / UPDATE_INTERVAL as f32;
+        let speed = gamestate.read().unwrap().gamestate.settings.player_speed * frame.min(MAX_FRAME_STEPS);
         let mut movement = (0.0, 0.0);
         if window.has_focus() {
             if Key::A.is_pressed() {
-                movement.0 -= 3.0;
+                movement.0 -= speed;
             }
             if Key::D.is_pressed() {
-                movement.0 += 3.0;
+                movement.0 += speed;
             }
             if Key::W.is_pressed() {
-                movement.1 -= 3.0;
+                movement.1 -= speed;
             }
             if Key::S.is_pressed() {
-                movement.1 += 3.0;
+                movement.1 += speed;
             }
             if Key::Z.is_pressed() {
                 displayer.zoom_in();
@@ -132,6 +126,13 @@ pub fn start_game(
                 displayer.zoom_out();
             }
         }
+        if gamestate.read().unwrap().stacks(&Item::Reverse) > 0 {
+            movement = (-movement.0, -movement.1);
+        }
+        // Nobody moves during the countdown or the summary
+        if gamestate.read().unwrap().gamestate.phase != Phase::Round {
+            movement = (0., 0.);
+        }
 
         {
             let mut gamestate = gamestate.write().unwrap();
@@ -140,8 +141,18 @@ pub fn start_game(
                 let mut player = gamestate.get_mut_player().unwrap();
                 player.move_player(movement);
             }
-            if let Some(player_room) = gamestate.player_room() {
-                if gamestate.player_in_door() {
+            // Ghosts go through walls and can't touch anything
+            let player_room = if gamestate.is_ghost() { None } else { gamestate.player_room() };
+            if let Some(player_room) = player_room {
+                if gamestate.player_blocked(movement) {
+                    if let Some(direction) = gamestate.player_locked_door() {
+                        if gamestate.get_player().unwrap().keys > 0 {
+                            gamestate.unlock = Some((player_room.position, direction));
+                        }
+                    }
+                    let mut player = gamestate.get_mut_player().unwrap();
+                    player.move_player((-movement.0, -movement.1))
+                } else if gamestate.player_in_door() {
                     let position = player_room.position;
                     if let TileType::Door(direction) =
                         player_room.tile(gamestate.player_tile()).tile_type
@@ -153,11 +164,8 @@ pub fn start_game(
                             Direction::Right => (position.0 + 1, position.1),
                         });
                     }
-                } else if gamestate.player_in_wall() {
-                    let mut player = gamestate.get_mut_player().unwrap();
-                    player.move_player((-movement.0, -movement.1))
                 } else if gamestate.player_in_exit() {
-                    if gamestate.gamestate.keys == 8 {
+                    if gamestate.exit_open {
                         gamestate.end = true;
                     } else {
                         let mut player = gamestate.get_mut_player().unwrap();
@@ -170,7 +178,7 @@ pub fn start_game(
             }
             let player = gamestate.get_player().unwrap();
             displayer.center_view(window, player);
-            // println!("player{:?}, in_wall{:?}, in_door{:?}, room{:?}, tile{:?}", gamestate.player_position(), gamestate.player_in_wall(), gamestate.player_in_wall(), gamestate.player_room_coord(), gamestate.player_tile());
+            // println!("player{:?}, in_wall{:?}, in_door{:?}, room{:?}, tile{:?}", gamestate.player_position(), gamestate.player_blocked(movement), gamestate.player_in_door(), gamestate.player_room_coord(), gamestate.player_tile());
         }
 
         displayer.display(window, Arc::clone(&gamestate));
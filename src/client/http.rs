@@ -1,14 +1,20 @@
 use reqwest;
+use tungstenite::{self, Message, WebSocket};
 
 use serde::{Deserialize, Serialize};
+use std::{io::ErrorKind, net::TcpStream};
 
+use lycan::shared::game_settings::GameSettings;
 use lycan::shared::http::{
-    JoinGameRequest, JoinGameResponse, NewGameRequest, NewGameResponse, UpdateRequest,
+    GameListResponse, GameSummary, JoinGameRequest, JoinGameResponse, LeaveGameRequest,
+    NewGameRequest, NewGameResponse, RejoinGameRequest, RejoinGameResponse, UpdateRequest,
     UpdateResponse,
 };
 
 type ClientResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+pub type Connection = WebSocket<TcpStream>;
+
 fn post<'a, T, S>(url: &str, payload: T) -> Result<S, Box<dyn std::error::Error>>
 where
     T: Serialize,
@@ -25,46 +31,95 @@ where
     }
 }
 
-pub fn new_game() -> ClientResult<String> {
-    let response: NewGameResponse =
-        post("http://localhost:1337/new", NewGameRequest { public: true }).unwrap();
-    Ok(response.game_id)
+fn get<S>(url: &str) -> Result<S, Box<dyn std::error::Error>>
+where
+    S: for<'de> Deserialize<'de>,
+{
+    let client = reqwest::blocking::Client::new();
+    match client.get(url).send() {
+        Ok(data) => Ok(data.json()?),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub fn list_games() -> ClientResult<Vec<GameSummary>> {
+    let response: GameListResponse = get("http://localhost:1337/games")?;
+    Ok(response.games)
+}
+
+pub fn new_game(public: bool, seed: Option<u64>, settings: GameSettings) -> ClientResult<NewGameResponse> {
+    post(
+        "http://localhost:1337/new",
+        NewGameRequest {
+            public,
+            seed,
+            settings: Some(settings),
+        },
+    )
 }
 
 pub fn join_game(game_id: &str, player_name: &str) -> ClientResult<JoinGameResponse> {
-    let response: JoinGameResponse = post(
+    post(
         "http://localhost:1337/join",
         JoinGameRequest {
             game_id: game_id.to_string(),
             player_name: player_name.to_string(),
         },
     )
-    .unwrap();
-    Ok(response)
 }
 
-pub fn update(
-    game_id: &str,
-    player_id: &str,
-    position: (f32, f32),
-    new_rooms: Vec<(i32, i32)>,
-    cleared_rooms: Vec<(i32, i32)>,
-    ready: bool,
-    end: bool,
-) -> ClientResult<UpdateResponse> {
-    let response: UpdateResponse = post(
-        "http://localhost:1337/update",
-        UpdateRequest {
+pub fn connect() -> ClientResult<Connection> {
+    let stream = TcpStream::connect("localhost:1337")?;
+    let (connection, _) = tungstenite::client("ws://localhost:1337/ws", stream)
+        .map_err(|err| err.to_string())?;
+    connection.get_ref().set_nonblocking(true)?;
+    Ok(connection)
+}
+
+fn would_block(err: &tungstenite::Error) -> bool {
+    match err {
+        tungstenite::Error::Io(err) => err.kind() == ErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+pub fn rejoin_game(game_id: &str, player_id: &str, token: &str) -> ClientResult<RejoinGameResponse> {
+    post(
+        "http://localhost:1337/rejoin",
+        RejoinGameRequest {
+            game_id: game_id.to_string(),
+            player_id: player_id.to_string(),
+            token: token.to_string(),
+        },
+    )
+}
+
+pub fn leave_game(game_id: &str, player_id: &str, token: &str) -> ClientResult<()> {
+    post(
+        "http://localhost:1337/leave",
+        LeaveGameRequest {
             game_id: game_id.to_string(),
             player_id: player_id.to_string(),
-            position,
-            new_rooms,
-            cleared_rooms,
-            ready,
-            end,
+            token: token.to_string(),
         },
     )
-    .unwrap();
+}
 
-    Ok(response)
+pub fn update(connection: &mut Connection, request: &UpdateRequest) -> ClientResult<()> {
+    match connection.write_message(Message::Text(serde_json::to_string(request)?)) {
+        Err(err) if !would_block(&err) => Err(Box::new(err)),
+        _ => Ok(()),
+    }
+}
+
+pub fn receive(connection: &mut Connection) -> ClientResult<Vec<UpdateResponse>> {
+    let mut responses = Vec::new();
+    loop {
+        match connection.read_message() {
+            Ok(Message::Text(text)) => responses.push(serde_json::from_str(&text)?),
+            Ok(_) => {}
+            Err(err) if would_block(&err) => return Ok(responses),
+            Err(err) => return Err(Box::new(err)),
+        }
+    }
 }
@@ -0,0 +1,85 @@
+use sfml::{
+    graphics::{
+        Font,
+        RenderWindow,
+    },
+};
+
+use lycan::shared::http::GameSummary;
+
+use crate::menu::{
+    menu::Menu,
+    button::Button,
+    label::Label,
+};
+
+const MAX_LISTED_GAMES: usize = 3;
+
+#[derive(Clone)]
+pub enum GameBrowserChoice {
+    Back,
+    Game(String),
+    Page(usize),
+}
+
+/// Lists `games` as sorted by the server, `MAX_LISTED_GAMES` at a time.
+pub fn game_browser(window: &mut RenderWindow, font: &Font, games: &[GameSummary], page: usize) -> GameBrowserChoice {
+    let pages = (games.len() + MAX_LISTED_GAMES - 1) / MAX_LISTED_GAMES;
+    let page = page.min(pages.saturating_sub(1));
+    let mut menu = Menu::new();
+    menu.add_widget(
+        Label::new(
+            &if games.is_empty() {
+                String::from("No public games")
+            } else {
+                format!("Public games {}/{}", page + 1, pages)
+            },
+            &font,
+            String::from("welcome"),
+        ),
+    );
+    for game in games.iter().skip(page * MAX_LISTED_GAMES).take(MAX_LISTED_GAMES) {
+        let status = if game.started {
+            format!("R{}", game.round)
+        } else {
+            String::from("lobby")
+        };
+        menu.add_widget(
+            Button::new(
+                &format!("{} {}p {}", game.game_id, game.players, status),
+                &font,
+                GameBrowserChoice::Game(game.game_id.clone()),
+                game.game_id.clone(),
+            ),
+        );
+    }
+    if page > 0 {
+        menu.add_widget(
+            Button::new(
+                "Previous",
+                &font,
+                GameBrowserChoice::Page(page - 1),
+                String::from("previous"),
+            ),
+        );
+    }
+    if page + 1 < pages {
+        menu.add_widget(
+            Button::new(
+                "Next",
+                &font,
+                GameBrowserChoice::Page(page + 1),
+                String::from("next"),
+            ),
+        );
+    }
+    menu.add_widget(
+        Button::new(
+            "Back",
+            &font,
+            GameBrowserChoice::Back,
+            String::from("back"),
+        ),
+    );
+    menu.handle(window)
+}
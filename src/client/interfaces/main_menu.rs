@@ -15,7 +15,10 @@ use crate::menu::{
 pub enum MainMenuChoice {
     Quit,
     NewGame,
+    /// A game left out of the browser, joined with its id.
+    NewPrivateGame,
     JoinGame,
+    BrowseGames,
 }
 
 pub fn main_menu(window: &mut RenderWindow, font: &Font) -> MainMenuChoice {
@@ -35,6 +38,14 @@ pub fn main_menu(window: &mut RenderWindow, font: &Font) -> MainMenuChoice {
             String::from("new"),
         ),
     );
+    menu.add_widget(
+        Button::new(
+            "New private game",
+            &font,
+            MainMenuChoice::NewPrivateGame,
+            String::from("new_private"),
+        ),
+    );
     menu.add_widget(
         Button::new(
             "Join game",
@@ -43,6 +54,14 @@ pub fn main_menu(window: &mut RenderWindow, font: &Font) -> MainMenuChoice {
             String::from("join"),
         ),
     );
+    menu.add_widget(
+        Button::new(
+            "Browse games",
+            &font,
+            MainMenuChoice::BrowseGames,
+            String::from("browse"),
+        ),
+    );
     menu.add_widget(
         Button::new(
             "Quit",
@@ -2,3 +2,6 @@ pub mod main_menu;
 pub mod name_entry;
 pub mod game_join;
 pub mod waiting_screen;
+pub mod game_browser;
+pub mod rejoin;
+pub mod results_screen;
@@ -0,0 +1,46 @@
+use sfml::{
+    graphics::{
+        Font,
+        RenderWindow,
+    },
+};
+
+use crate::menu::{
+    menu::Menu,
+    button::Button,
+    label::Label,
+};
+
+#[derive(Clone)]
+pub enum RejoinChoice {
+    Rejoin,
+    Menu,
+}
+
+pub fn rejoin(window: &mut RenderWindow, font: &Font, game_id: &str) -> RejoinChoice {
+    let mut menu = Menu::new();
+    menu.add_widget(
+        Label::new(
+            &format!("Lost connection to game {}", game_id),
+            &font,
+            String::from("welcome"),
+        ),
+    );
+    menu.add_widget(
+        Button::new(
+            "Rejoin",
+            &font,
+            RejoinChoice::Rejoin,
+            String::from("rejoin"),
+        ),
+    );
+    menu.add_widget(
+        Button::new(
+            "Leave game",
+            &font,
+            RejoinChoice::Menu,
+            String::from("menu"),
+        ),
+    );
+    menu.handle(window)
+}
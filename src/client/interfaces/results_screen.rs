@@ -0,0 +1,67 @@
+use std::{
+    thread,
+    time::{Duration},
+};
+use sfml::{
+    graphics::{
+        Color,
+        Font,
+        Text,
+        RenderTarget,
+        RenderWindow,
+        Transformable,
+    },
+    system::{
+        Vector2,
+    },
+    window::{Event, Key},
+};
+
+use lycan::shared::gamestate::{Role, RoundResult};
+
+/// Shows who won each round of the match until the player goes back to the menu.
+pub fn results_screen(window: &mut RenderWindow, font: &Font, results: &[RoundResult]) {
+    let traitor_won = results.iter().any(|result| result.winner == Role::Traitor);
+    let mut lines = vec![if traitor_won {
+        String::from("The traitor wins the match")
+    } else {
+        String::from("The explorers win the match")
+    }];
+    for result in results {
+        let mut line = match result.winner {
+            Role::Explorer => format!("Round {}: {} found the exit", result.round, result.player),
+            Role::Traitor => format!("Round {}: {} took a fake exit", result.round, result.player),
+        };
+        if let Some(traitor) = &result.traitor {
+            line = format!("{}, {} was the traitor", line, traitor);
+        }
+        lines.push(line);
+    }
+    lines.push(String::from("Press enter to go back to the menu"));
+
+    loop {
+        while let Some(event) = window.poll_event() {
+            match event {
+                Event::Closed
+                | Event::KeyPressed {code: Key::Return, ..}
+                | Event::KeyPressed {code: Key::Escape, ..} => return,
+                _ => {}
+            }
+        }
+        window.clear(Color::BLACK);
+        let size = window.size();
+        for (index, line) in lines.iter().enumerate() {
+            let mut text = Text::default();
+            text.set_font(font);
+            text.set_string(line);
+            text.set_character_size(if index == 0 { 30 } else { 20 });
+            text.set_fill_color(if index == 0 { Color::RED } else { Color::WHITE });
+            let text_size = text.local_bounds();
+            text.set_origin((text_size.width as f32/2., text_size.height as f32/2.));
+            text.set_position(Vector2::from((size.x as f32/2., 100. + index as f32 * 50.)));
+            window.draw(&text);
+        }
+        window.display();
+        thread::sleep(Duration::from_millis(15));
+    }
+}
@@ -6,13 +6,11 @@ use std::{
 };
 use sfml::{
     graphics::{
-        CircleShape,
         Color,
         Font,
         Text,
         RenderTarget,
         RenderWindow,
-        Shape,
         Transformable,
     },
     system::{
@@ -21,11 +19,12 @@ use sfml::{
     window::{Event, Key},
 };
 
-use crate::menu::{
-    menu::Menu,
-    button::Button,
-    text_field::TextField,
-};
+use lycan::shared::game_settings::{GameSettings, CURSES};
+use lycan::shared::gamestate::Phase;
+
+/// Built in so the rules show up wherever the client is started from.
+const README: &str = include_str!("../../../README.org");
+const RULES_SECTION: &str = "* Rules";
 
 #[derive(Clone)]
 pub enum WaitingScreenChoice {
@@ -33,55 +32,155 @@ pub enum WaitingScreenChoice {
     Ready,
 }
 
+/// The rules section of the README.
+fn rules_text() -> Vec<String> {
+    README
+        .lines()
+        .skip_while(|line| line.trim() != RULES_SECTION)
+        .skip(1)
+        .take_while(|line| !line.starts_with('*'))
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn draw_text(window: &mut RenderWindow, font: &Font, string: &str, position: (f32, f32), size: u32, color: Color) {
+    let mut text = Text::default();
+    text.set_font(font);
+    text.set_string(string);
+    text.set_character_size(size);
+    text.set_fill_color(color);
+    text.set_position(Vector2::from(position));
+    window.draw(&text);
+}
+
 pub fn waiting_screen(window: &mut RenderWindow, font: &Font, gamestate: Arc<RwLock<ClientGamestate>>) -> WaitingScreenChoice {
-    println!("waiting started");
+    let rules_text = rules_text();
+    // The host's changes the server hasn't sent back yet, so quick presses build on each other
+    let mut pending: Option<GameSettings> = None;
     while !gamestate.read().unwrap().is_started() {
+        if gamestate.read().unwrap().disconnected {
+            return WaitingScreenChoice::Back;
+        }
+        {
+            let gamestate = gamestate.read().unwrap();
+            if !gamestate.is_host() || gamestate.gamestate.phase != Phase::Lobby || pending.as_ref() == Some(&gamestate.gamestate.settings) {
+                pending = None;
+            }
+        }
         while let Some(event) = window.poll_event() {
             if !window.has_focus() {
                 continue
             }
+            let mut gamestate = gamestate.write().unwrap();
+            let settings = pending.clone().unwrap_or_else(|| gamestate.gamestate.settings.clone());
             match event {
                 Event::KeyPressed {code: Key::Return, ..} =>  {
-                    let mut gamestate = gamestate.write().unwrap();
                     let mut player = gamestate.get_mut_player().unwrap();
                     player.ready = !player.ready;
-                    println!("{}", player.ready);
                 }
                 Event::KeyPressed {code: Key::Escape, ..} =>  {
                     return WaitingScreenChoice::Back;
                 }
+                // The host picks the settings and can kick players by their number in the list
+                Event::KeyPressed {code, ..} if gamestate.is_host() => {
+                    let changed = match code {
+                        Key::Left => Some(GameSettings { keys: settings.keys.saturating_sub(1), ..settings }),
+                        Key::Right => Some(GameSettings { keys: settings.keys + 1, ..settings }),
+                        Key::Down => Some(GameSettings { rounds: settings.rounds.saturating_sub(1), ..settings }),
+                        Key::Up => Some(GameSettings { rounds: settings.rounds + 1, ..settings }),
+                        Key::LBracket => Some(GameSettings { map_radius: settings.map_radius - 1, ..settings }),
+                        Key::RBracket => Some(GameSettings { map_radius: settings.map_radius + 1, ..settings }),
+                        Key::Comma => Some(GameSettings { rooms: settings.rooms.saturating_sub(10), ..settings }),
+                        Key::Period => Some(GameSettings { rooms: settings.rooms + 10, ..settings }),
+                        Key::V => Some(GameSettings { style: settings.style.next(), ..settings }),
+                        Key::Dash => Some(GameSettings { player_speed: settings.player_speed - 0.5, ..settings }),
+                        Key::Equal => Some(GameSettings { player_speed: settings.player_speed + 0.5, ..settings }),
+                        code if code >= Key::Num1 && code <= Key::Num9 && Key::K.is_pressed() => {
+                            let index = code as usize - Key::Num1 as usize;
+                            let kicked = gamestate.lobby_players().get(index).map(|(id, _)| (*id).clone());
+                            gamestate.kick = kicked;
+                            None
+                        }
+                        code if code >= Key::Num1 && (code as usize) < Key::Num1 as usize + CURSES.len() => {
+                            let mut settings = settings;
+                            settings.toggle_curse(&CURSES[code as usize - Key::Num1 as usize]);
+                            Some(settings)
+                        }
+                        _ => None,
+                    };
+                    if let Some(changed) = changed {
+                        let changed = changed.clamped();
+                        pending = Some(changed.clone());
+                        gamestate.settings = Some(changed);
+                    }
+                }
                 _ => {}
             }
         }
         window.clear(Color::BLACK);
         let size = window.size();
+        let gamestate = gamestate.read().unwrap();
+
         let mut title_text = Text::default();
         let mut game_id = Text::default();
-        let mut circle = CircleShape::new(100., 100);
-        if gamestate.read().unwrap().get_player().unwrap().ready {
+        if let Some(seconds) = gamestate.countdown_seconds() {
+            title_text.set_string(&format!("Starting in {}, press enter to cancel", seconds));
+            title_text.set_fill_color(Color::YELLOW);
+        } else if gamestate.get_player().unwrap().ready {
             title_text.set_string("Waiting for players");
-            circle.set_fill_color(Color::GREEN);
+            title_text.set_fill_color(Color::GREEN);
         } else {
             title_text.set_string("Press enter when ready");
-            circle.set_fill_color(Color::RED);
+            title_text.set_fill_color(Color::RED);
         }
         title_text.set_font(font);
         game_id.set_font(font);
-        game_id.set_string(gamestate.read().unwrap().game_id.as_ref().unwrap());
+        game_id.set_string(gamestate.game_id.as_ref().unwrap());
         let text_size = title_text.local_bounds();
         title_text.set_origin((text_size.width as f32/2., text_size.height as f32/2.));
         let game_id_size = game_id.local_bounds();
         game_id.set_origin((game_id_size.width as f32/2., game_id_size.height as f32/2.));
-        title_text.set_position(Vector2::from((size.x as f32/2., size.y as f32/2. - 200.)));
-        game_id.set_position(Vector2::from((size.x as f32/2., size.y as f32/2.)));
-        circle.set_origin((100., 100.));
-        circle.set_position(Vector2::from((size.x as f32/2., size.y as f32/2.)));
-        if gamestate.read().unwrap().get_player().unwrap().ready {
-        } else {
-        }
-        window.draw(&title_text);
-        window.draw(&circle);
+        game_id.set_position(Vector2::from((size.x as f32/2., 30.)));
+        title_text.set_position(Vector2::from((size.x as f32/2., 80.)));
         window.draw(&game_id);
+        window.draw(&title_text);
+
+        draw_text(window, font, "Players", (40., 130.), 20, Color::WHITE);
+        for (index, (id, player)) in gamestate.lobby_players().iter().enumerate() {
+            let mut line = format!("{}. {}", index + 1, player.name);
+            if gamestate.host.as_ref() == Some(*id) {
+                line = format!("{} (host)", line);
+            }
+            let color = if player.ready { Color::GREEN } else { Color::RED };
+            draw_text(window, font, &line, (40., 160. + index as f32 * 22.), 16, color);
+        }
+
+        let settings = pending.as_ref().unwrap_or(&gamestate.gamestate.settings);
+        let curses: Vec<String> = settings.curses.iter().map(|curse| curse.to_string()).collect();
+        let mut settings_lines = vec![
+            String::from("Settings"),
+            format!("Keys needed: {}", settings.keys),
+            format!("Exits to find: {}", settings.rounds),
+            format!("Map radius: {}", settings.map_radius),
+            format!("Rooms: {}", settings.rooms),
+            format!("Cave: {}", settings.style.to_string()),
+            format!("Speed: {:.1}", settings.player_speed),
+            format!("Curses: {}", if curses.is_empty() { String::from("none") } else { curses.join(" ") }),
+        ];
+        if gamestate.is_host() {
+            settings_lines.push(String::from("Left/Right: keys, Up/Down: exits"));
+            settings_lines.push(String::from("[/]: map, ,/.: rooms, -/=: speed"));
+            settings_lines.push(String::from("V: cave, 1-5: curses, K + number: kick"));
+        }
+        for (index, line) in settings_lines.iter().enumerate() {
+            let character_size = if index == 0 { 20 } else { 14 };
+            draw_text(window, font, line, (size.x as f32/2. + 20., 130. + index as f32 * 20.), character_size, Color::WHITE);
+        }
+
+        for (index, line) in rules_text.iter().enumerate() {
+            draw_text(window, font, line, (40., 360. + index as f32 * 18.), 14, Color::rgb(200, 200, 200));
+        }
         window.display();
         thread::sleep(Duration::from_millis(15));
     }
@@ -14,6 +14,7 @@ use std::{
 };
 
 mod game;
+mod session;
 mod settings;
 mod menu;
 mod interfaces;
@@ -35,16 +36,30 @@ use interfaces::{
         game_join,
         GameJoinChoice,
     },
+    game_browser::{
+        game_browser,
+        GameBrowserChoice,
+    },
+    rejoin::{
+        rejoin,
+        RejoinChoice,
+    },
+    results_screen::results_screen,
 };
 
 use client_state::ClientGamestate;
 use game::{start_game, GameResult};
 // use main_menu::{MenuChoice};
 
+use session::Session;
 use settings::Settings;
 
+use lycan::shared::room_template::ROOM_TEMPLATES;
+use lycan::shared::utils::UPDATE_INTERVAL;
+
 
 fn main() {
+    lazy_static::initialize(&ROOM_TEMPLATES);
     let font = Font::from_file("src/client/resources/VCR_OSD_MONO_1.001.ttf").unwrap().to_owned();
     let mut gamestate: Arc<RwLock<ClientGamestate>> = Arc::new(
         RwLock::new(
@@ -67,52 +82,60 @@ fn main() {
     );
     window.set_vertical_sync_enabled(true);
 
-    loop {
-        match main_menu(&mut window, &font) {
+    'menu: loop {
+        // Left over by a crash or a dropped connection
+        if let Some(session) = Session::load() {
+            if let Some(GameResult::Quit) = resume(&mut window, &font, Arc::clone(&gamestate), session) {
+                break;
+            }
+            continue;
+        }
+        let choice = main_menu(&mut window, &font);
+        match choice {
             MainMenuChoice::Quit => break,
-            MainMenuChoice::NewGame => {
-                new_game(Arc::clone(&gamestate));
-                match name_entry(&mut window, &font) {
-                    NameEntryChoice::Back => continue,
-                    NameEntryChoice::Name(name) => {
-                        join_game(Arc::clone(&gamestate), &name);
-
-                        start_update_loop(Arc::clone(&gamestate));
-                        match waiting_screen(&mut window, &font, Arc::clone(&gamestate)) {
-                            WaitingScreenChoice::Back => continue,
-                            WaitingScreenChoice::Ready => {
-                                match start_game(&mut window, Arc::clone(&gamestate)) {
-                                    GameResult::Menu => continue,
-                                    GameResult::Quit => break,
-                                };
-                            }
-                        };
-                    },
-                };
+            MainMenuChoice::NewGame | MainMenuChoice::NewPrivateGame => {
+                let public = matches!(choice, MainMenuChoice::NewGame);
+                if !new_game(Arc::clone(&gamestate), &setting, public) {
+                    continue;
+                }
+                if let Some(GameResult::Quit) = play(&mut window, &font, Arc::clone(&gamestate)) {
+                    break;
+                }
             },
             MainMenuChoice::JoinGame => {
                 match game_join(&mut window, &font) {
                     GameJoinChoice::Back => continue,
                     GameJoinChoice::Game(game_id) => {
                         gamestate.write().unwrap().set_game(game_id);
-
-                        match name_entry(&mut window, &font) {
-                            NameEntryChoice::Back => continue,
-                            NameEntryChoice::Name(name) => {
-                                join_game(Arc::clone(&gamestate), &name);
-
-                                start_update_loop(Arc::clone(&gamestate));
-                                match waiting_screen(&mut window, &font, Arc::clone(&gamestate)) {
-                                    WaitingScreenChoice::Back => continue,
-                                    WaitingScreenChoice::Ready => {
-                                        match start_game(&mut window, Arc::clone(&gamestate)) {
-                                            GameResult::Menu => continue,
-                                            GameResult::Quit => break,
-                                        };
-                                    }
-                                };
-                            },
-                        };
+                        if let Some(GameResult::Quit) = play(&mut window, &font, Arc::clone(&gamestate)) {
+                            break;
+                        }
+                    }
+                }
+            }
+            MainMenuChoice::BrowseGames => {
+                let mut page = 0;
+                // Fetched again every time it's shown, games come and go
+                loop {
+                    let games = match http::list_games() {
+                        Ok(games) => games,
+                        Err(err) => {
+                            println!("{}", err);
+                            break;
+                        }
+                    };
+                    match game_browser(&mut window, &font, &games, page) {
+                        GameBrowserChoice::Back => break,
+                        GameBrowserChoice::Page(next) => page = next,
+                        GameBrowserChoice::Game(game_id) => {
+                            gamestate.write().unwrap().set_game(game_id);
+                            match play(&mut window, &font, Arc::clone(&gamestate)) {
+                                Some(GameResult::Quit) => break 'menu,
+                                Some(_) => break,
+                                // Backed out or the game is gone, pick another one
+                                None => {}
+                            }
+                        }
                     }
                 }
             }
@@ -120,51 +143,156 @@ fn main() {
     }
 }
 
-fn new_game(gamestate: Arc<RwLock<ClientGamestate>>) {
-    match http::new_game() {
-        Ok(game_id) => {
-            println!("{}", game_id);
+/// Asks for a player name, joins the selected game and plays it.
+/// Returns `None` when the player backs out before the game starts or the game can't be joined.
+fn play(window: &mut RenderWindow, font: &Font, gamestate: Arc<RwLock<ClientGamestate>>) -> Option<GameResult> {
+    match name_entry(window, font) {
+        NameEntryChoice::Back => None,
+        NameEntryChoice::Name(name) => {
+            if !join_game(Arc::clone(&gamestate), &name) {
+                return None;
+            }
+            enter_game(window, font, gamestate)
+        },
+    }
+}
+
+/// Offers to rejoin the game of a session the player never left.
+fn resume(window: &mut RenderWindow, font: &Font, gamestate: Arc<RwLock<ClientGamestate>>, session: Session) -> Option<GameResult> {
+    match rejoin(window, font, &session.game_id) {
+        RejoinChoice::Rejoin => match http::rejoin_game(&session.game_id, &session.player_id, &session.token) {
+            Ok(response) => {
+                gamestate
+                    .write()
+                    .unwrap()
+                    .rejoin(session.game_id, session.player_id, session.token, response);
+                enter_game(window, font, gamestate)
+            }
+            Err(err) => {
+                println!("{}", err);
+                Session::clear();
+                None
+            }
+        },
+        RejoinChoice::Menu => {
+            if let Err(err) = http::leave_game(&session.game_id, &session.player_id, &session.token) {
+                println!("{}", err);
+            }
+            Session::clear();
+            None
+        }
+    }
+}
+
+/// Plays the game the player has joined, from the waiting screen until they leave it.
+/// A dropped connection keeps the session so the player can rejoin.
+fn enter_game(window: &mut RenderWindow, font: &Font, gamestate: Arc<RwLock<ClientGamestate>>) -> Option<GameResult> {
+    start_update_loop(Arc::clone(&gamestate));
+    let result = match waiting_screen(window, font, Arc::clone(&gamestate)) {
+        WaitingScreenChoice::Back => None,
+        WaitingScreenChoice::Ready => match start_game(window, Arc::clone(&gamestate)) {
+            GameResult::End => {
+                let results = gamestate.read().unwrap().gamestate.results.clone();
+                results_screen(window, font, &results);
+                Some(GameResult::Menu)
+            }
+            result => Some(result),
+        },
+    };
+    if gamestate.read().unwrap().disconnected {
+        *gamestate.write().unwrap() = ClientGamestate::default();
+    } else {
+        leave_game(Arc::clone(&gamestate));
+    }
+    result
+}
+
+/// Returns whether the server made the game.
+fn new_game(gamestate: Arc<RwLock<ClientGamestate>>, setting: &Settings, public: bool) -> bool {
+    match http::new_game(public, setting.seed, setting.game.clone()) {
+        Ok(response) => {
+            println!("{} (seed {})", response.game_id, response.seed);
             let mut gamestate = gamestate.write().unwrap();
-            gamestate.set_game(game_id);
+            gamestate.set_game(response.game_id);
+            true
+        }
+        Err(err) => {
+            println!("{}", err);
+            false
         }
-        Err(err) => println!("{}", err),
     }
 }
 
-fn join_game(gamestate: Arc<RwLock<ClientGamestate>>, player_name: &str) {
+/// Returns whether the game could be joined.
+fn join_game(gamestate: Arc<RwLock<ClientGamestate>>, player_name: &str) -> bool {
     let game_id = gamestate.read().unwrap().game_id.as_ref().unwrap().clone();
     match http::join_game(&game_id, player_name) {
         Ok(response) => {
             println!("ok");
             println!("{:?}", response);
             let mut gamestate = gamestate.write().unwrap();
+            Session {
+                game_id: game_id.clone(),
+                player_id: response.player_id.clone(),
+                token: response.token.clone(),
+            }
+            .save();
             gamestate.set_game(game_id);
-            gamestate.set_player(response.player_id, response.position);
+            gamestate.set_player(response.player_id, response.token, response.position);
             println!("game set");
+            true
+        }
+        Err(err) => {
+            println!("{}", err);
+            false
         }
-        Err(err) => println!("{}", err),
     }
 }
 
 
+fn leave_game(gamestate: Arc<RwLock<ClientGamestate>>) {
+    let (game_id, player_id, token) = {
+        let gamestate = gamestate.read().unwrap();
+        (gamestate.get_game_id(), gamestate.get_player_id(), gamestate.token.clone())
+    };
+    if let Err(err) = http::leave_game(&game_id, &player_id, &token) {
+        println!("{}", err);
+    }
+    Session::clear();
+    *gamestate.write().unwrap() = ClientGamestate::default();
+}
+
 fn start_update_loop(gamestate: Arc<RwLock<ClientGamestate>>) {
     let thread_gamestate = Arc::clone(&gamestate);
-    thread::spawn(move || loop {
-        {
-            let game_id = thread_gamestate.read().unwrap().get_game_id().clone();
-            let player_id = thread_gamestate.read().unwrap().get_player_id().clone();
-            let position = thread_gamestate.read().unwrap().get_player().unwrap().position;
-            let ready = thread_gamestate.read().unwrap().get_player().unwrap().ready;
-            let new_rooms = thread_gamestate.write().unwrap().get_new_rooms();
-            let cleared_rooms = thread_gamestate.write().unwrap().get_cleared_rooms();
-            let end = thread_gamestate.write().unwrap().end;
-            match http::update(&game_id, &player_id, position, new_rooms, cleared_rooms, ready, end) {
-                Ok(data) => {
-                    thread_gamestate.write().unwrap().update(data);
+    let player_id = gamestate.read().unwrap().get_player_id();
+    thread::spawn(move || {
+        let mut connection = match http::connect() {
+            Ok(connection) => connection,
+            Err(err) => return println!("{}", err),
+        };
+        loop {
+            {
+                // Stop once the player left this game
+                let request = match thread_gamestate.write().unwrap().update_request() {
+                    Some(request) if request.player_id == player_id => request,
+                    _ => return,
+                };
+                if let Err(err) = http::update(&mut connection, &request) {
+                    println!("{}", err);
                 }
-                Err(err) => println!("{}", err),
-            };
+                match http::receive(&mut connection) {
+                    Ok(responses) => {
+                        for data in responses {
+                            thread_gamestate.write().unwrap().update(data);
+                        }
+                    }
+                    Err(err) => {
+                        thread_gamestate.write().unwrap().disconnected = true;
+                        return println!("{}", err);
+                    }
+                };
+            }
+            thread::sleep(Duration::from_millis(UPDATE_INTERVAL));
         }
-        thread::sleep(Duration::from_millis(15));
     });
 }
@@ -90,10 +90,17 @@ impl<'a, T> Menu<'a, T> where T: Clone {
     fn render(&mut self, window: &mut RenderWindow) {
         window.clear(Color::rgb(60, 44, 41));
         let size = window.size();
+        let (mut top, mut spacing) = ((size.y/4) as f32, 100.);
+        let last = self.widgets.len().saturating_sub(1) as f32;
+        // Squeeze longer menus so the last widget stays on screen
+        if top + last * spacing + 50. > size.y as f32 {
+            top = (size.y/8) as f32;
+            spacing = ((size.y as f32 - 50. - top) / last).min(spacing);
+        }
         for (index, widget) in self.widgets.iter_mut().enumerate() {
             widget.set_position((
                 (size.x/2) as f32,
-                (size.y/4) as f32 + index as f32 * 100.,
+                top + index as f32 * spacing,
             ));
             window.draw(widget);
         }
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+const SESSION_FILE: &str = "lycan_session.json";
+
+/// The game the client is currently part of, kept on disk so a crashed client can rejoin it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub game_id: String,
+    pub player_id: String,
+    pub token: String,
+}
+
+impl Session {
+    pub fn load() -> Option<Session> {
+        let data = fs::read_to_string(SESSION_FILE).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(data) => {
+                if let Err(err) = fs::write(SESSION_FILE, data) {
+                    println!("{}", err);
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    pub fn clear() {
+        let _ = fs::remove_file(SESSION_FILE);
+    }
+}
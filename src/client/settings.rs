@@ -1,11 +1,23 @@
+use lycan::shared::game_settings::GameSettings;
+use lycan::shared::utils::ROUND_TARGET;
+
 pub struct Settings {
     pub resolution: (u32, u32),
+    /// Cave seed for the games we create, set through `LYCAN_SEED`.
+    pub seed: Option<u64>,
+    /// Settings of the games we create, exits to find are set through `LYCAN_ROUNDS`.
+    pub game: GameSettings,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             resolution: (800, 600),
+            seed: std::env::var("LYCAN_SEED").ok().and_then(|seed| seed.parse().ok()),
+            game: GameSettings {
+                rounds: std::env::var("LYCAN_ROUNDS").ok().and_then(|rounds| rounds.parse().ok()).unwrap_or(ROUND_TARGET),
+                ..GameSettings::default()
+            },
         }
     }
 }
@@ -1,19 +1,30 @@
 mod server_state;
 
 use bytes::buf::BufExt;
+use futures_util::{SinkExt, StreamExt};
 use hyper::service::{make_service_fn, service_fn};
+use hyper::upgrade::Upgraded;
 use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use sha1::{Digest, Sha1};
 use std::fmt;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_tungstenite::{tungstenite, WebSocketStream};
 
 use server_state::ServerState;
 
 use serde::{Deserialize, Serialize};
 
 use lycan::shared::http::{
-    JoinGameRequest, JoinGameResponse, NewGameRequest, NewGameResponse, UpdateRequest,
-    UpdateResponse,
+    GameListResponse, JoinGameRequest, JoinGameResponse, LeaveGameRequest, NewGameRequest,
+    NewGameResponse, RejoinGameRequest, UpdateRequest,
 };
+use lycan::shared::room_template::ROOM_TEMPLATES;
+use lycan::shared::utils::UPDATE_INTERVAL;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+
+const WEBSOCKET_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, GenericError>;
@@ -65,41 +76,149 @@ fn join_game(request: JoinGameRequest, state: State) -> Result<String> {
         .unwrap()
         .join_game(request.game_id, request.player_name)
     {
-        Some(player) => data(JoinGameResponse {
-            player_id: player.0,
-            position: player.1,
+        Some((player_id, token, position)) => data(JoinGameResponse {
+            player_id,
+            token,
+            position,
         }),
         None => not_found(),
     }
 }
 
 fn new_game(request: NewGameRequest, state: State) -> Result<String> {
-    let game_id = state.write().unwrap().new_game(request.public);
-    data(NewGameResponse { game_id })
-}
-
-fn update(request: UpdateRequest, state: State) -> Result<String> {
-    match state.write().unwrap().update(
-        request.game_id.clone(),
-        request.player_id.clone(),
-        request.position,
-        request.new_rooms,
-        request.cleared_rooms,
-        request.ready,
-        request.end,
-    ) {
-        Some(gamestate) => data(UpdateResponse::new(gamestate)),
+    let seed = request.seed.unwrap_or_else(rand::random);
+    let settings = request.settings.unwrap_or_default();
+    let game_id = state.write().unwrap().new_game(request.public, seed, settings);
+    data(NewGameResponse { game_id, seed })
+}
+
+fn rejoin_game(request: RejoinGameRequest, state: State) -> Result<String> {
+    match state
+        .write()
+        .unwrap()
+        .rejoin_game(&request.game_id, &request.player_id, &request.token)
+    {
+        Some(response) => data(response),
+        None => not_found(),
+    }
+}
+
+fn leave_game(request: LeaveGameRequest, state: State) -> Result<String> {
+    match state
+        .write()
+        .unwrap()
+        .leave_game(&request.game_id, &request.player_id, &request.token)
+    {
+        Some(()) => data(()),
+        None => not_found(),
+    }
+}
+
+fn list_games(state: State) -> Result<String> {
+    let games = state.read().unwrap().public_games();
+    data(GameListResponse { games })
+}
+
+fn update(request: UpdateRequest, state: &State) -> Result<(String, String, u64)> {
+    let player = (request.game_id.clone(), request.player_id.clone(), request.ack);
+    match state.write().unwrap().update(request) {
+        Some(()) => Ok(player),
         None => not_found(),
     }
 }
 
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::default();
+    sha1.update(key);
+    sha1.update(WEBSOCKET_GUID);
+    base64::encode(&sha1.finalize())
+}
+
+async fn websocket(request: Request<Body>, state: State) -> Result<Response<Body>> {
+    let upgrade = request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("expected a websocket upgrade"))?);
+    }
+    let key = match request.headers().get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) => accept_key(key.as_bytes()),
+        None => return not_found(),
+    };
+
+    tokio::spawn(async move {
+        match request.into_body().on_upgrade().await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(
+                    upgraded,
+                    tungstenite::protocol::Role::Server,
+                    None,
+                )
+                .await;
+                if let Err(err) = session(socket, state).await {
+                    println!("{}", err);
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::UPGRADE, "websocket")
+        .header(header::CONNECTION, "upgrade")
+        .header(header::SEC_WEBSOCKET_ACCEPT, key)
+        .body(Body::empty())?)
+}
+
+/// Applies the `UpdateRequest`s pushed by a client as they arrive and pushes the
+/// `UpdateResponse` for its player back every `UPDATE_INTERVAL`.
+async fn session(socket: WebSocketStream<Upgraded>, state: State) -> Result<()> {
+    let (mut sink, mut stream) = socket.split();
+    let mut ticker = tokio::time::interval(Duration::from_millis(UPDATE_INTERVAL));
+    let mut player: Option<(String, String, u64)> = None;
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                // A bad frame or a game that's gone only loses that update, not the session
+                Some(Ok(tungstenite::Message::Text(text))) => {
+                    match serde_json::from_str(&text).map_err(GenericError::from).and_then(|request| update(request, &state)) {
+                        Ok(updated) => player = Some(updated),
+                        Err(err) => println!("Skipped update: {}", err),
+                    }
+                }
+                Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(Box::new(err)),
+            },
+            _ = ticker.tick() => {
+                if let Some((game_id, player_id, ack)) = &player {
+                    let response = match state.write().unwrap().state(game_id, player_id, *ack) {
+                        Some(response) => data(response)?,
+                        None => return Ok(()),
+                    };
+                    sink.send(tungstenite::Message::Text(response)).await?;
+                }
+            }
+        }
+    }
+}
+
 async fn router(req: Request<Body>, state: State) -> Result<Response<Body>> {
     let (method, path) = (req.method(), req.uri().path());
 
     let data = match (method, path) {
         (&Method::POST, "/new") => new_game(parse(req).await?, state),
         (&Method::POST, "/join") => join_game(parse(req).await?, state),
-        (&Method::POST, "/update") => update(parse(req).await?, state),
+        (&Method::POST, "/leave") => leave_game(parse(req).await?, state),
+        (&Method::POST, "/rejoin") => rejoin_game(parse(req).await?, state),
+        (&Method::GET, "/games") => list_games(state),
+        (&Method::GET, "/ws") => return websocket(req, state).await,
         _ => return not_found(),
     };
 
@@ -112,11 +231,22 @@ async fn router(req: Request<Body>, state: State) -> Result<Response<Body>> {
 #[tokio::main]
 async fn main() -> Result<()> {
     pretty_env_logger::init();
+    // Fail right away on a broken room template rather than in the middle of a game
+    lazy_static::initialize(&ROOM_TEMPLATES);
 
     let addr = "0.0.0.0:1337".parse().unwrap();
 
     let state: State = Arc::new(RwLock::new(ServerState::new()));
 
+    let cleanup_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            cleanup_state.write().unwrap().cleanup();
+        }
+    });
+
     let new_service = make_service_fn(move |_| {
         let state = Arc::clone(&state);
         async { Ok::<_, GenericError>(service_fn(move |req| router(req, Arc::clone(&state)))) }
@@ -1,120 +1,1004 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::{HashMap, HashSet}, f32::consts::SQRT_2, fmt::Debug, time::{Duration, Instant}};
 
 use uuid::Uuid;
 use rand;
 
-use lycan::shared::gamestate::{Gamestate, Player, Message, Map};
-use lycan::shared::room::{Item};
+use lycan::shared::effects::{EffectRule, Effects, Reveal};
+use lycan::shared::gamestate::{Gamestate, Player, PlayerState, Message, Phase, Role, RoundResult, PLAYER_COLORS};
+use lycan::shared::game_settings::GameSettings;
+use lycan::shared::http::{GameSummary, RejoinGameResponse, UpdateRequest, UpdateResponse};
+use lycan::shared::map_generator::MapGenerator;
+use lycan::shared::room::{Item, Room, RoomType, TileType};
+use lycan::shared::room_template::ROOM_TEMPLATES;
+use lycan::shared::utils::{distance, room_coord, tile_center, tile_coord, Direction, GIVE_DISTANCE, ROOM_SIZE, TILE_SIZE, UPDATE_INTERVAL};
+
+const MAX_MOVE_DISTANCE: f32 = 256.;
+/// Update intervals of movement a player can save up, enough to absorb network jitter without
+/// letting anyone sprint.
+const MAX_MOVE_ALLOWANCE: f32 = 10.;
+const PLAYER_TIMEOUT: Duration = Duration::from_secs(30);
+const EMPTY_GAME_TIMEOUT: Duration = Duration::from_secs(120);
+const COUNTDOWN_DURATION: Duration = Duration::from_secs(5);
+const SUMMARY_DURATION: Duration = Duration::from_secs(8);
+/// Markers a ghost can have on the map at once, placing another one removes their oldest.
+const MAX_MARKERS: usize = 3;
+
+/// The tile of `floor` closest to `tile`, `tile` itself when it's one of them.
+fn nearest_floor(floor: &[(u32, u32)], tile: (i32, i32)) -> Option<(u32, u32)> {
+    floor.iter().cloned().min_by_key(|floor| {
+        let (x, y) = (floor.0 as i32 - tile.0, floor.1 as i32 - tile.1);
+        x * x + y * y
+    })
+}
 
 #[derive(Debug)]
 pub struct ServerGamestate {
     pub gamestate: Gamestate,
-    pub curses: HashMap<String, bool>,
+    pub public: bool,
+    pub seed: u64,
+    pub roles: HashMap<String, Role>,
+    pub last_updates: HashMap<String, Instant>,
+    /// How far each player may still move during the round, with when it was last topped up.
+    pub move_allowances: HashMap<String, (Instant, f32)>,
+    pub departed: HashMap<String, Player>,
+    pub explored: HashMap<String, HashMap<(i32, i32), u64>>,
+    pub explored_sequences: HashMap<(i32, i32), u64>,
+    pub last_activity: Instant,
+    pub created: Instant,
+    pub corrections: HashMap<String, (f32, f32)>,
+    pub sequence: u64,
+    pub snapshot_sequence: u64,
+    pub room_sequences: HashMap<(i32, i32), u64>,
+    pub player_sequences: HashMap<String, u64>,
+    pub message_sequences: Vec<u64>,
+    pub resync: HashSet<String>,
+    pub fake_exit: Option<(i32, i32)>,
+    pub effects: Effects,
+    /// When the countdown or the round summary is over.
+    pub phase_end: Option<Instant>,
+    /// Ghost markers with the ghost who left them, oldest first.
+    pub markers: Vec<(String, (f32, f32))>,
+    /// Curses each player picked up this round that everyone was told were keys. They're counted
+    /// in the keys players are shown, only the real ones open the exit.
+    pub disguised_keys: HashMap<String, u32>,
+    /// The player who picks the settings and can kick others, the first one to join.
+    pub host: Option<String>,
+    /// Secret each player got when joining, kept while they're departed so they can rejoin.
+    pub tokens: HashMap<String, String>,
 }
 
 impl ServerGamestate {
-    pub fn new() -> ServerGamestate {
-        let mut default_map: Vec<(i32, i32)> = vec![(-15, 0), (-14, -1), (-14, 0), (-14, 1), (-13, -2), (-13, 0), (-13, 2), (-12, -3), (-12, -2), (-12, -1), (-12, 0), (-12, 1), (-12, 2), (-12, 3), (-11, -4), (-11, -2), (-11, 0), (-11, 2), (-11, 4), (-10, -5), (-10, -4), (-10, -3), (-10, -1), (-10, 0), (-10, 1), (-10, 3), (-10, 4), (-10, 5), (-9, -6), (-9, -4), (-9, -2), (-9, 0), (-9, 2), (-9, 4), (-9, 6), (-8, -7), (-8, -6), (-8, -5), (-8, -4), (-8, -3), (-8, -2), (-8, -1), (-8, 0), (-8, 1), (-8, 2), (-8, 3), (-8, 4), (-8, 5), (-8, 6), (-8, 7), (-7, -8), (-7, -6), (-7, -4), (-7, -2), (-7, 0), (-7, 2), (-7, 4), (-7, 6), (-7, 8), (-6, -9), (-6, -8), (-6, -7), (-6, -5), (-6, -4), (-6, -3), (-6, -1), (-6, 0), (-6, 1), (-6, 3), (-6, 4), (-6, 5), (-6, 7), (-6, 8), (-6, 9), (-5, -10), (-5, -8), (-5, -6), (-5, -4), (-5, -2), (-5, 0), (-5, 2), (-5, 4), (-5, 6), (-5, 8), (-5, 10), (-4, -11), (-4, -10), (-4, -9), (-4, -8), (-4, -7), (-4, -6), (-4, -5), (-4, -3), (-4, -2), (-4, -1), (-4, 0), (-4, 1), (-4, 2), (-4, 3), (-4, 5), (-4, 6), (-4, 7), (-4, 8), (-4, 9), (-4, 10), (-4, 11), (-3, -12), (-3, -10), (-3, -8), (-3, -6), (-3, -4), (-3, -2), (-3, 0), (-3, 2), (-3, 4), (-3, 6), (-3, 8), (-3, 10), (-3, 12), (-2, -13), (-2, -12), (-2, -11), (-2, -9), (-2, -8), (-2, -7), (-2, -5), (-2, -4), (-2, -3), (-2, -1), (-2, 0), (-2, 1), (-2, 3), (-2, 4), (-2, 5), (-2, 7), (-2, 8), (-2, 9), (-2, 11), (-2, 12), (-2, 13), (-1, -14), (-1, -12), (-1, -10), (-1, -8), (-1, -6), (-1, -4), (-1, -2), (-1, 0), (-1, 2), (-1, 4), (-1, 6), (-1, 8), (-1, 10), (-1, 12), (-1, 14), (0, -15), (0, -14), (0, -13), (0, -12), (0, -11), (0, -10), (0, -9), (0, -8), (0, -7), (0, -6), (0, -5), (0, -4), (0, -3), (0, -2), (0, -1), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5), (0, 6), (0, 7), (0, 8), (0, 9), (0, 10), (0, 11), (0, 12), (0, 13), (0, 14), (0, 15), (1, -14), (1, -12), (1, -10), (1, -8), (1, -6), (1, -4), (1, -2), (1, 0), (1, 2), (1, 4), (1, 6), (1, 8), (1, 10), (1, 12), (1, 14), (2, -13), (2, -12), (2, -11), (2, -9), (2, -8), (2, -7), (2, -5), (2, -4), (2, -3), (2, -1), (2, 0), (2, 1), (2, 3), (2, 4), (2, 5), (2, 7), (2, 8), (2, 9), (2, 11), (2, 12), (2, 13), (3, -12), (3, -10), (3, -8), (3, -6), (3, -4), (3, -2), (3, 0), (3, 2), (3, 4), (3, 6), (3, 8), (3, 10), (3, 12), (4, -11), (4, -10), (4, -9), (4, -8), (4, -7), (4, -6), (4, -5), (4, -3), (4, -2), (4, -1), (4, 0), (4, 1), (4, 2), (4, 3), (4, 5), (4, 6), (4, 7), (4, 8), (4, 9), (4, 10), (4, 11), (5, -10), (5, -8), (5, -6), (5, -4), (5, -2), (5, 0), (5, 2), (5, 4), (5, 6), (5, 8), (5, 10), (6, -9), (6, -8), (6, -7), (6, -5), (6, -4), (6, -3), (6, -1), (6, 0), (6, 1), (6, 3), (6, 4), (6, 5), (6, 7), (6, 8), (6, 9), (7, -8), (7, -6), (7, -4), (7, -2), (7, 0), (7, 2), (7, 4), (7, 6), (7, 8), (8, -7), (8, -6), (8, -5), (8, -4), (8, -3), (8, -2), (8, -1), (8, 0), (8, 1), (8, 2), (8, 3), (8, 4), (8, 5), (8, 6), (8, 7), (9, -6), (9, -4), (9, -2), (9, 0), (9, 2), (9, 4), (9, 6), (10, -5), (10, -4), (10, -3), (10, -1), (10, 0), (10, 1), (10, 3), (10, 4), (10, 5), (11, -4), (11, -2), (11, 0), (11, 2), (11, 4), (12, -3), (12, -2), (12, -1), (12, 0), (12, 1), (12, 2), (12, 3), (13, -2), (13, 0), (13, 2), (14, -1), (14, 0), (14, 1), (15, 0), (0, 0), (-17, 0), (17, 0), (0, 17), (0, -17)];
-
+    pub fn new(public: bool, seed: u64, settings: GameSettings) -> ServerGamestate {
         let mut gamestate = Gamestate::default();
+        gamestate.settings = settings.clamped();
         let mut server_gamestate = ServerGamestate {
             gamestate,
-            curses: HashMap::new(),
+            public,
+            seed,
+            roles: HashMap::new(),
+            last_updates: HashMap::new(),
+            move_allowances: HashMap::new(),
+            disguised_keys: HashMap::new(),
+            departed: HashMap::new(),
+            explored: HashMap::new(),
+            explored_sequences: HashMap::new(),
+            last_activity: Instant::now(),
+            created: Instant::now(),
+            corrections: HashMap::new(),
+            sequence: 1,
+            snapshot_sequence: 1,
+            room_sequences: HashMap::new(),
+            player_sequences: HashMap::new(),
+            message_sequences: vec![],
+            resync: HashSet::new(),
+            fake_exit: None,
+            effects: Effects::default(),
+            phase_end: None,
+            markers: vec![],
+            host: None,
+            tokens: HashMap::new(),
         };
+        server_gamestate.generate_map();
+        server_gamestate
+    }
 
+    /// Every round gets its own map, still reproducible from the game's seed.
+    pub fn generate_map(&mut self) {
+        let seed = self.seed.wrapping_add(self.gamestate.round as u64);
+        self.gamestate.map = MapGenerator::with_settings(seed, &self.gamestate.settings).generate();
+    }
 
-        let mut index = 0;
-        while let Some(room) = default_map.get(index) {
-            if server_gamestate.gamestate.map.room_degree(*room) == 1 {
-                server_gamestate.add_room(*room);
-                default_map.remove(index);
-                index = 0
-            } else {
-                index += 1;
-            }
-        }
-
-        for item in &[
-            Item::Clear,
-            Item::Spin,
-            Item::Bad,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-            Item::Key,
-        ] {
-            let mut room_x = rand::random::<i32>() % 31 + 15;
-            let mut room_y = rand::random::<i32>() % 31 + 15;
-            while server_gamestate.gamestate.map.room(room_x, room_y).is_none() || room_x == 0 && room_y == 0 {
-                room_x = rand::random::<i32>() % 31 + 15;
-                room_y = rand::random::<i32>() % 31 + 15;
-            }
-            let x = rand::random::<u32>() % 8 + 4;
-            let y = rand::random::<u32>() % 8 + 4;
-            server_gamestate.gamestate.map.mut_room(room_x, room_y).unwrap().item = Some((item.clone(), (x, y)));
+    /// Lets the host change the settings before the match starts. When the cave changes it's made
+    /// again, with everyone in a new spot.
+    pub fn set_settings(&mut self, player_id: &String, settings: GameSettings) -> Option<()> {
+        let settings = settings.clamped();
+        if self.host.as_ref() != Some(player_id) || self.gamestate.phase != Phase::Lobby || settings == self.gamestate.settings {
+            return None;
+        }
+        let same_map = settings.same_map(&self.gamestate.settings);
+        self.gamestate.settings = settings;
+        if same_map {
+            return Some(());
         }
+        self.generate_map();
+        let player_ids: Vec<String> = self.gamestate.players.keys().cloned().collect();
+        for player_id in player_ids {
+            let position = self.spawn_position();
+            self.gamestate.players.get_mut(&player_id)?.position = position;
+        }
+        self.snapshot_sequence = self.next_sequence();
+        Some(())
+    }
 
-        server_gamestate
+    /// Removes a player for good, wherever they are.
+    pub fn forget_player(&mut self, player_id: &String) -> Option<Player> {
+        let player = match self.remove_player(player_id) {
+            Some(player) => player,
+            None => self.departed.remove(player_id)?,
+        };
+        self.explored.remove(player_id);
+        self.roles.remove(player_id);
+        self.effects.remove_player(player_id);
+        self.disguised_keys.remove(player_id);
+        self.tokens.remove(player_id);
+        Some(player)
+    }
+
+    /// Player ids are in every response, only their token proves who is asking.
+    pub fn authorize(&self, player_id: &String, token: &String) -> Option<()> {
+        match self.tokens.get(player_id) {
+            Some(expected) if expected == token => Some(()),
+            _ => None,
+        }
+    }
+
+    /// Only in the lobby, kicking someone in the middle of a round could decide it.
+    pub fn kick(&mut self, player_id: &String, kicked_id: &String) -> Option<()> {
+        if self.host.as_ref() != Some(player_id) || player_id == kicked_id || self.gamestate.phase != Phase::Lobby {
+            return None;
+        }
+        let player = self.forget_player(kicked_id)?;
+        self.add_message(format!("{} has been kicked", player.name));
+        Some(())
     }
 
     pub fn next_round(&mut self) {
-        self.gamestate.map = Map::default();
+        self.generate_map();
         self.gamestate.keys = 0;
         self.gamestate.round += 1;
         self.gamestate.messages = vec![];
-        self.curses = HashMap::new();
-        for (_id, player) in self.gamestate.players.iter_mut() {
-            player.position = (
-                ((rand::random::<i32>() % 16 - 8) * 16 * 16 + 16*8) as f32,
-                ((rand::random::<i32>() % 16 - 8) * 16 * 16 + 16*8) as f32,
-            );
+        self.fake_exit = None;
+        self.effects.clear();
+        self.markers.clear();
+        self.disguised_keys.clear();
+        // Rooms explored last round are somewhere else, or nowhere, in the new map
+        self.explored.clear();
+        self.explored_sequences.clear();
+        // Departed players too, their old position may be inside a wall of the new map once they rejoin
+        let positions: Vec<(f32, f32)> = (0..self.gamestate.players.len() + self.departed.len())
+            .map(|_| self.spawn_position())
+            .collect();
+        let players = self.gamestate.players.values_mut().chain(self.departed.values_mut());
+        for (player, position) in players.zip(positions) {
+            player.position = position;
+            player.keys = 0;
+            player.inventory.clear();
+        }
+        self.room_sequences.clear();
+        self.player_sequences.clear();
+        self.message_sequences.clear();
+        self.snapshot_sequence = self.next_sequence();
+    }
+
+    pub fn role(&self, player_id: &String) -> Role {
+        self.roles.get(player_id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_ghost(&self, player_id: &String) -> bool {
+        self.gamestate
+            .players
+            .get(player_id)
+            .map_or(false, |player| player.state == PlayerState::Ghost)
+    }
+
+    /// The traitor and ghosts see every room anyone explored.
+    pub fn sees_everything(&self, player_id: &String) -> bool {
+        self.role(player_id) == Role::Traitor || self.is_ghost(player_id)
+    }
+
+    /// Turns a defeated traitor into a ghost for the rest of the match.
+    pub fn make_ghost(&mut self, player_id: &String) -> Option<()> {
+        let player = match self.gamestate.players.get_mut(player_id) {
+            Some(player) => player,
+            None => self.departed.get_mut(player_id)?,
+        };
+        player.state = PlayerState::Ghost;
+        player.inventory.clear();
+        let name = player.name.clone();
+        self.touch_player(player_id);
+        self.resync.insert(player_id.clone());
+        self.add_message(format!("{} now haunts the cave as a ghost", name));
+        Some(())
+    }
+
+    /// Leaves a marker where the ghost stands, removing their oldest one past `MAX_MARKERS`.
+    pub fn place_marker(&mut self, player_id: &String) -> Option<()> {
+        if !self.is_ghost(player_id) {
+            return None;
+        }
+        let position = self.gamestate.players.get(player_id)?.position;
+        let placed: Vec<usize> = self
+            .markers
+            .iter()
+            .enumerate()
+            .filter(|(_, (ghost, _))| ghost == player_id)
+            .map(|(index, _)| index)
+            .collect();
+        if placed.len() >= MAX_MARKERS {
+            self.markers.remove(placed[0]);
+        }
+        self.markers.push((player_id.clone(), position));
+        Some(())
+    }
+
+    pub fn traitor(&self) -> Option<&String> {
+        self.roles
+            .iter()
+            .find(|(_, role)| **role == Role::Traitor)
+            .map(|(player_id, _)| player_id)
+    }
+
+    /// Keys held by the players still in the game, which is what opens the exit.
+    pub fn count_keys(&mut self) {
+        self.gamestate.keys = self.gamestate.players.values().map(|player| player.keys).sum();
+    }
+
+    /// Opens a locked door of the room `player_id` is in with one of their keys.
+    pub fn unlock(&mut self, player_id: &String, room: (i32, i32), direction: &Direction) -> Option<()> {
+        let player = self.gamestate.players.get(player_id)?;
+        if player.keys == 0 || room_coord(player.position) != room {
+            return None;
+        }
+        let name = player.name.clone();
+        if !self.gamestate.map.unlock(room, direction) {
+            return None;
+        }
+        self.gamestate.players.get_mut(player_id)?.keys -= 1;
+        self.count_keys();
+        let offset = direction.offset();
+        self.touch_room(room);
+        self.touch_room((room.0 + offset.0, room.1 + offset.1));
+        self.touch_player(player_id);
+        self.add_message(format!("{} has unlocked a door", name));
+        Some(())
+    }
+
+    /// Leaves one of the player's keys somewhere on the floor of their room, if nothing is there yet.
+    pub fn drop_key(&mut self, player_id: &String) -> Option<()> {
+        let player = self.gamestate.players.get(player_id)?;
+        if player.keys == 0 {
+            return None;
+        }
+        let room_position = room_coord(player.position);
+        let tile = tile_coord(player.position);
+        let room = self.gamestate.map.room(room_position.0, room_position.1)?;
+        if room.item.is_some() {
+            return None;
+        }
+        // Not under the player's feet, or they would pick it right back up
+        let floor: Vec<(u32, u32)> = ROOM_TEMPLATES
+            .get(room.room_type.template())
+            .floor_tiles()
+            .into_iter()
+            .filter(|floor| (floor.0 as i32, floor.1 as i32) != tile)
+            .collect();
+        if floor.is_empty() {
+            return None;
+        }
+        let drop = floor[rand::random::<usize>() % floor.len()];
+        self.gamestate.map.mut_room(room_position.0, room_position.1)?.item = Some((Item::Key, drop));
+        self.gamestate.players.get_mut(player_id)?.keys -= 1;
+        self.count_keys();
+        self.touch_room(room_position);
+        self.touch_player(player_id);
+        Some(())
+    }
+
+    /// Leaves `keys` keys on the floor of the rooms closest to `from` that can be walked to from
+    /// it and have nothing lying in them yet, one per room.
+    pub fn scatter_keys(&mut self, from: (i32, i32), keys: u32) {
+        let reachable = self.gamestate.map.reachable(from);
+        let mut rooms: Vec<(i32, i32)> = reachable
+            .into_iter()
+            .filter(|position| match self.gamestate.map.room(position.0, position.1) {
+                Some(room) => room.item.is_none() && matches!(room.room_type, RoomType::Basic | RoomType::Template(_)),
+                None => false,
+            })
+            .collect();
+        rooms.sort_by_key(|position| ((position.0 - from.0).abs() + (position.1 - from.1).abs(), *position));
+        for position in rooms.into_iter().take(keys as usize) {
+            let room = match self.gamestate.map.mut_room(position.0, position.1) {
+                Some(room) => room,
+                None => continue,
+            };
+            let floor = ROOM_TEMPLATES.get(room.room_type.template()).floor_tiles();
+            if floor.is_empty() {
+                continue;
+            }
+            room.item = Some((Item::Key, floor[rand::random::<usize>() % floor.len()]));
+            self.touch_room(position);
+        }
+    }
+
+    pub fn give_key(&mut self, player_id: &String, teammate_id: &String) -> Option<()> {
+        let player = self.gamestate.players.get(player_id)?;
+        let teammate = self.gamestate.players.get(teammate_id)?;
+        if teammate.state == PlayerState::Ghost || player.keys == 0 || player_id == teammate_id || distance(player.position, teammate.position) > GIVE_DISTANCE {
+            return None;
+        }
+        let message = format!("{} has handed a key to {}", player.name, teammate.name);
+        self.gamestate.players.get_mut(player_id)?.keys -= 1;
+        self.gamestate.players.get_mut(teammate_id)?.keys += 1;
+        self.touch_player(player_id);
+        self.touch_player(teammate_id);
+        self.add_message(message);
+        Some(())
+    }
+
+    /// Uses the item in `slot` of the player's inventory on `target`, the player themselves by default.
+    pub fn use_item(&mut self, player_id: &String, slot: usize, target: Option<String>) -> Option<()> {
+        let target = target.unwrap_or_else(|| player_id.clone());
+        let player = self.gamestate.players.get(player_id)?;
+        let teammate = self.gamestate.players.get(&target)?;
+        if teammate.state == PlayerState::Ghost || slot >= player.inventory.len() || distance(player.position, teammate.position) > GIVE_DISTANCE {
+            return None;
+        }
+        let (name, teammate_name) = (player.name.clone(), teammate.name.clone());
+        let item = self.gamestate.players.get_mut(player_id)?.inventory.remove(slot);
+        self.take_disguised_key(player_id, &item);
+        self.touch_player(player_id);
+        if &target != player_id {
+            self.add_private_message(player_id, format!("You used a {} on {}", item.to_string(), teammate_name));
+            self.add_private_message(&target, format!("{} used a {} on you", name, item.to_string()));
+        }
+        self.apply_item(&target, item);
+        Some(())
+    }
+
+    /// Milliseconds since the game was created, the clock effects run on.
+    pub fn now(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    /// Applies the effect of `item` used on `player_id`, to whoever its rule targets.
+    pub fn apply_item(&mut self, player_id: &String, item: Item) {
+        let rule = match EffectRule::of(&item) {
+            Some(rule) => rule,
+            None => return,
+        };
+        let player_ids: Vec<String> = self
+            .gamestate
+            .players
+            .iter()
+            .filter(|(_, player)| player.state == PlayerState::Alive)
+            .map(|(player_id, _)| player_id.clone())
+            .collect();
+        let targets = rule.targets(player_id, &player_ids, rand::random());
+        let now = self.now();
+        for target in targets {
+            if rule.confusion && self.role(&target) == Role::Traitor {
+                continue;
+            }
+            if !self.effects.apply(&target, &item, now) {
+                continue;
+            }
+            match item {
+                Item::Clear => {
+                    // Forget everything they explored, they'll have to find their way again
+                    self.explored.remove(&target);
+                    self.resync.insert(target.clone());
+                }
+                Item::Teleport => {
+                    self.teleport(&target);
+                }
+                Item::Bad => {
+                    self.make_traitor(&target);
+                }
+                _ => {}
+            }
+            if rule.confusion {
+                self.add_private_message(&target, String::from("You have been cursed!"));
+            }
+        }
+    }
+
+    /// Moves a player to a random floor tile of a room they explored, other than the one they're in.
+    pub fn teleport(&mut self, player_id: &String) -> Option<()> {
+        let current = room_coord(self.gamestate.players.get(player_id)?.position);
+        let rooms: Vec<(i32, i32)> = self
+            .explored
+            .get(player_id)?
+            .keys()
+            .filter(|room| **room != current)
+            .cloned()
+            .collect();
+        if rooms.is_empty() {
+            return None;
+        }
+        let room = rooms[rand::random::<usize>() % rooms.len()];
+        let floor = ROOM_TEMPLATES
+            .get(self.gamestate.map.room(room.0, room.1)?.room_type.template())
+            .floor_tiles();
+        if floor.is_empty() {
+            return None;
+        }
+        let position = tile_center(room, floor[rand::random::<usize>() % floor.len()]);
+        self.gamestate.players.get_mut(player_id)?.position = position;
+        self.corrections.insert(player_id.clone(), position);
+        self.touch_player(player_id);
+        Some(())
+    }
+
+    pub fn give_item(&mut self, player_id: &String, slot: usize, teammate_id: &String) -> Option<()> {
+        let player = self.gamestate.players.get(player_id)?;
+        let teammate = self.gamestate.players.get(teammate_id)?;
+        if teammate.state == PlayerState::Ghost || slot >= player.inventory.len() || player_id == teammate_id || distance(player.position, teammate.position) > GIVE_DISTANCE {
+            return None;
+        }
+        let message = format!("{} handed you a {}", player.name, player.inventory[slot].to_string());
+        let item = self.gamestate.players.get_mut(player_id)?.inventory.remove(slot);
+        // Still a key as far as everyone else knows
+        if self.take_disguised_key(player_id, &item) {
+            *self.disguised_keys.entry(teammate_id.clone()).or_insert(0) += 1;
+        }
+        self.gamestate.players.get_mut(teammate_id)?.inventory.push(item);
+        self.touch_player(player_id);
+        self.touch_player(teammate_id);
+        self.add_private_message(teammate_id, message);
+        Some(())
+    }
+
+    /// Secretly turns `player_id` into the traitor, unless someone already is one.
+    pub fn make_traitor(&mut self, player_id: &String) -> bool {
+        if self.traitor().is_some() {
+            return false;
+        }
+        self.roles.insert(player_id.clone(), Role::Traitor);
+        // The traitor needs everything explored so far, not just what changed
+        self.resync.insert(player_id.clone());
+        true
+    }
+
+    /// Ends the round in favour of `winner`, revealing the traitor if there was one.
+    /// `player` is the name of whoever took the exit.
+    pub fn end_round(&mut self, winner: Role, player: String) {
+        let traitor_id = self.traitor().cloned();
+        let traitor = traitor_id
+            .as_ref()
+            .and_then(|player_id| self.gamestate.players.get(player_id).or_else(|| self.departed.get(player_id)))
+            .map(|player| player.name.clone());
+        self.roles.clear();
+        match winner {
+            Role::Explorer => self.add_message(format!("{} has found the exit!", player)),
+            Role::Traitor => self.add_message(format!("{} took a fake exit!", player)),
+        }
+        match (winner, &traitor) {
+            (Role::Explorer, Some(name)) => self.add_message(format!("{} was the traitor and has been defeated!", name)),
+            (Role::Traitor, Some(name)) => self.add_message(format!("{} was the traitor and led everyone astray!", name)),
+            _ => {}
+        }
+        if let (Role::Explorer, Some(traitor_id)) = (winner, &traitor_id) {
+            self.make_ghost(traitor_id);
         }
+        self.gamestate.results.push(RoundResult {
+            round: self.gamestate.round,
+            winner,
+            player,
+            traitor,
+        });
+        self.set_phase(Phase::Summary, Some(SUMMARY_DURATION));
     }
 
-    pub fn add_room(&mut self, position: (i32, i32)) -> bool {
-        self.gamestate.add_room(position)
+    /// Exits found by the explorers so far.
+    pub fn exits_found(&self) -> u32 {
+        self.gamestate
+            .results
+            .iter()
+            .filter(|result| result.winner == Role::Explorer)
+            .count() as u32
     }
 
-    pub fn curse(&mut self, curse: Item) -> Item {
-        match self.curses.get(&curse.to_string()) {
-            Some(true) => Item::Key,
-            _ => {
-                self.curses.insert(curse.to_string(), true);
-                curse
+    /// The explorers win the match once they found enough exits, the traitor as soon as they win a round.
+    pub fn match_over(&self) -> bool {
+        self.exits_found() >= self.gamestate.settings.rounds
+            || self.gamestate.results.iter().any(|result| result.winner == Role::Traitor)
+    }
+
+    pub fn set_phase(&mut self, phase: Phase, duration: Option<Duration>) {
+        self.gamestate.phase = phase;
+        self.phase_end = duration.map(|duration| Instant::now() + duration);
+    }
+
+    /// Moves the match along once everyone is ready or a countdown or summary is over.
+    /// Anyone cancelling their ready during the first countdown sends everyone back to the lobby.
+    pub fn tick(&mut self, now: Instant) {
+        let over = self.phase_end.map_or(false, |end| now >= end);
+        let ready = !self.gamestate.players.is_empty() && self.all_players_ready();
+        match self.gamestate.phase {
+            Phase::Lobby if ready => self.set_phase(Phase::Countdown, Some(COUNTDOWN_DURATION)),
+            Phase::Countdown if !self.gamestate.started && !ready => self.set_phase(Phase::Lobby, None),
+            Phase::Countdown if over => {
+                if !self.gamestate.started {
+                    // Out of the lobby, where everyone could see everyone
+                    self.snapshot_sequence = self.next_sequence();
+                }
+                self.gamestate.started = true;
+                self.set_phase(Phase::Round, None);
             }
+            Phase::Summary if over && self.match_over() => {
+                let message = if self.exits_found() >= self.gamestate.settings.rounds {
+                    String::from("The explorers have escaped, they win the match!")
+                } else {
+                    String::from("The traitor wins the match!")
+                };
+                self.set_phase(Phase::End, None);
+                self.add_message(message);
+            }
+            Phase::Summary if over => {
+                self.next_round();
+                self.set_phase(Phase::Countdown, Some(COUNTDOWN_DURATION));
+            }
+            _ => {}
         }
     }
 
+    /// Rooms `player_id` can see with the sequence they became visible at,
+    /// the traitor and ghosts see everything explored by anyone.
+    pub fn visible_rooms(&self, player_id: &String) -> Option<&HashMap<(i32, i32), u64>> {
+        if self.sees_everything(player_id) {
+            Some(&self.explored_sequences)
+        } else {
+            self.explored.get(player_id)
+        }
+    }
 
-    pub fn add_player(&mut self, uuid: String, player_name: String) {
-        let mut coords = (1, 1);
-        while self.gamestate.map.room(coords.0, coords.1).is_none() || coords.0 == 0 && coords.1 == 0 {
-            coords = (
-                ((rand::random::<i32>() % 16 - 8)),
-                ((rand::random::<i32>() % 16 - 8)),
-            );
+    /// Fake exits look like the real one to everyone but the traitor,
+    /// and every item lying around looks like a key until someone picks it up.
+    pub fn disguise(&self, player_id: &String, room: &Room) -> Room {
+        let mut room = room.disguised();
+        if room.is_fake_exit() && self.role(player_id) != Role::Traitor {
+            room.room_type = RoomType::Exit;
         }
+        room
+    }
+
+    /// Disguised keys of the players in the game, the ones who left took theirs with them.
+    fn shown_disguised_keys(&self) -> u32 {
+        self.disguised_keys
+            .iter()
+            .filter(|(id, _)| self.gamestate.players.contains_key(*id))
+            .map(|(_, keys)| keys)
+            .sum()
+    }
+
+    /// Stops counting `item` in the keys `player_id` is shown with now that it left their
+    /// inventory, if it was one everyone was told was a key.
+    fn take_disguised_key(&mut self, player_id: &String, item: &Item) -> bool {
+        let reveal = EffectRule::of(item).map_or(Reveal::Picker, |rule| rule.reveal);
+        match self.disguised_keys.get_mut(player_id) {
+            Some(keys) if *keys > 0 && reveal != Reveal::Everyone => {
+                *keys -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Tells whoever the curse's rule says what the key `player_id` picked up really was.
+    pub fn reveal_curse(&mut self, player_id: &String, item: &Item) -> Option<()> {
+        let name = self.gamestate.players.get(player_id)?.name.clone();
+        let reveal = EffectRule::of(item).map_or(Reveal::Picker, |rule| rule.reveal);
+        match reveal {
+            Reveal::Everyone => {
+                self.add_message(format!("{} has picked up a cursed key: {}!", name, item.to_string()));
+            }
+            Reveal::Picker => {
+                self.add_message(format!("{} has picked up a key!", name));
+                self.add_private_message(player_id, format!("The key was cursed: {}", item.to_string()));
+                *self.disguised_keys.entry(player_id.clone()).or_insert(0) += 1;
+            }
+            Reveal::Nobody => {
+                self.add_message(format!("{} has picked up a key!", name));
+                *self.disguised_keys.entry(player_id.clone()).or_insert(0) += 1;
+            }
+        }
+        self.touch_player(player_id);
+        Some(())
+    }
+
+    /// Turns a regular room next to the traitor into a fake exit, once per round.
+    pub fn spawn_fake_exit(&mut self, player_id: &String, position: (i32, i32)) -> Option<()> {
+        if self.role(player_id) != Role::Traitor || self.fake_exit.is_some() {
+            return None;
+        }
+        let player_room = room_coord(self.gamestate.players.get(player_id)?.position);
+        if (player_room.0 - position.0).abs() + (player_room.1 - position.1).abs() > 1 {
+            return None;
+        }
+        let room = self.gamestate.map.mut_room(position.0, position.1)?;
+        match room.room_type {
+            RoomType::Basic | RoomType::Template(_) => room.room_type = RoomType::FakeExit,
+            _ => return None,
+        }
+        // The room takes the exit layout, move what ended up in its walls or on the exit
+        let template = ROOM_TEMPLATES.get(room.room_type.template());
+        let floor = template.floor_tiles();
+        if let Some((item, tile)) = room.item.clone() {
+            if let Some(tile) = nearest_floor(&floor, (tile.0 as i32, tile.1 as i32)) {
+                room.item = Some((item, tile));
+            }
+        }
+        let stuck: Vec<(String, (i32, i32))> = self
+            .gamestate
+            .players
+            .iter()
+            .filter(|(_, player)| player.state == PlayerState::Alive && room_coord(player.position) == position)
+            .map(|(id, player)| (id.clone(), tile_coord(player.position)))
+            .filter(|(_, tile)| match template.tile(*tile) {
+                TileType::Wall(_) | TileType::Pit | TileType::None => true,
+                _ => false,
+            })
+            .collect();
+        for (id, tile) in stuck {
+            if let Some(tile) = nearest_floor(&floor, tile) {
+                let moved = tile_center(position, tile);
+                self.gamestate.players.get_mut(&id)?.position = moved;
+                self.corrections.insert(id.clone(), moved);
+                self.touch_player(&id);
+            }
+        }
+        self.fake_exit = Some(position);
+        self.touch_room(position);
+        Some(())
+    }
+
+    /// Rooms explored by anyone since `since`, which only the traitor gets to see.
+    pub fn revealed_rooms(&self, player_id: &String, since: u64) -> Vec<(i32, i32)> {
+        if !self.sees_everything(player_id) {
+            return vec![];
+        }
+        self.explored_sequences
+            .iter()
+            .filter(|(_, sequence)| **sequence > since)
+            .map(|(position, _)| *position)
+            .collect()
+    }
+
+    pub fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    pub fn touch_room(&mut self, position: (i32, i32)) {
+        let sequence = self.next_sequence();
+        self.room_sequences.insert(position, sequence);
+    }
+
+    pub fn touch_player(&mut self, player_id: &String) {
+        let sequence = self.next_sequence();
+        self.player_sequences.insert(player_id.clone(), sequence);
+    }
+
+    pub fn add_message(&mut self, text: String) {
+        let sequence = self.next_sequence();
+        self.gamestate.messages.push(Message::new(text));
+        self.message_sequences.push(sequence);
+    }
+
+    pub fn add_private_message(&mut self, player_id: &String, text: String) {
+        let sequence = self.next_sequence();
+        self.gamestate.messages.push(Message::private(text, player_id.clone()));
+        self.message_sequences.push(sequence);
+    }
+
+    /// Builds the changes since `ack` that `player_id` is allowed to see, falling back to a full
+    /// snapshot when the client has nothing yet or acknowledged a sequence we can't diff from.
+    pub fn response(&mut self, player_id: &String, ack: u64) -> UpdateResponse {
+        let correction = self.corrections.remove(player_id);
+        let resync = self.resync.remove(player_id);
+        let snapshot = resync || ack < self.snapshot_sequence || ack > self.sequence;
+        let since = if snapshot { 0 } else { ack };
+        let no_rooms = HashMap::new();
+        let visible = self.visible_rooms(player_id).unwrap_or(&no_rooms);
+        let changed_since = |sequences: Option<&u64>| sequences.map_or(false, |sequence| *sequence > since);
+        let rooms = visible
+            .iter()
+            .filter(|(position, seen)| **seen > since || changed_since(self.room_sequences.get(position)))
+            .filter_map(|(position, _)| self.gamestate.map.room(position.0, position.1))
+            .map(|room| self.disguise(player_id, room))
+            .collect();
+        let mut players = HashMap::new();
+        let mut hidden_players = vec![];
+        for (id, player) in &self.gamestate.players {
+            let changed = snapshot || changed_since(self.player_sequences.get(id));
+            // Everyone is listed in the lobby, just not where they are
+            let seen = if id == player_id || !self.gamestate.started {
+                Some(&0)
+            } else if player.state == PlayerState::Ghost && !self.is_ghost(player_id) {
+                None
+            } else {
+                visible.get(&room_coord(player.position))
+            };
+            match seen {
+                Some(seen) if changed || *seen > since => {
+                    let mut player = player.clone();
+                    player.keys += self.disguised_keys.get(id).copied().unwrap_or(0);
+                    if id != player_id {
+                        player.inventory.clear();
+                        if !self.gamestate.started {
+                            player.position = (0., 0.);
+                        }
+                    }
+                    players.insert(id.clone(), player);
+                }
+                None if changed => hidden_players.push(id.clone()),
+                _ => {}
+            }
+        }
+        let messages: Vec<(&u64, &Message)> = self
+            .message_sequences
+            .iter()
+            .zip(self.gamestate.messages.iter())
+            .filter(|(_, message)| message.visible_to(player_id))
+            .collect();
+        let messages_from = messages
+            .iter()
+            .take_while(|(sequence, _)| **sequence <= since)
+            .count();
+        UpdateResponse {
+            sequence: self.sequence,
+            since,
+            snapshot,
+            players,
+            hidden_players,
+            rooms,
+            started: self.gamestate.started,
+            keys: self.gamestate.keys + self.shown_disguised_keys(),
+            exit_open: self.exit_open(),
+            messages_from,
+            messages: messages[messages_from..].iter().map(|(_, message)| (*message).clone()).collect(),
+            round: self.gamestate.round,
+            phase: self.gamestate.phase,
+            countdown: match self.gamestate.phase {
+                Phase::Countdown => self.phase_end.map(|end| {
+                    end.saturating_duration_since(Instant::now()).as_millis() as u64
+                }),
+                _ => None,
+            },
+            settings: self.gamestate.settings.clone(),
+            host: self.host.clone(),
+            results: self.gamestate.results.clone(),
+            correction,
+            role: self.role(player_id),
+            revealed_rooms: self.revealed_rooms(player_id, since),
+            effects: self.effects.active(player_id, self.now()),
+            markers: self
+                .markers
+                .iter()
+                .map(|(_, position)| *position)
+                .filter(|position| visible.contains_key(&room_coord(*position)))
+                .collect(),
+        }
+    }
+
+
+    /// Center of a random room other than the exit, on the exit's side of any locked door.
+    pub fn spawn_position(&self) -> (f32, f32) {
+        let rooms: Vec<(i32, i32)> = self
+            .gamestate
+            .map
+            .reachable((0, 0))
+            .into_iter()
+            .filter(|position| *position != (0, 0))
+            .collect();
+        let coords = match rooms.len() {
+            0 => (0, 0),
+            count => rooms[rand::random::<usize>() % count],
+        };
+        let center = ROOM_SIZE as u32 / 2;
+        tile_center(coords, (center, center))
+    }
+
+    /// The first color nobody in the game has, players who left included since they can rejoin.
+    fn pick_color(&self) -> (u8, u8, u8) {
+        let taken: Vec<(u8, u8, u8)> = self
+            .gamestate
+            .players
+            .values()
+            .chain(self.departed.values())
+            .map(|player| player.color)
+            .collect();
+        match PLAYER_COLORS.iter().find(|color| !taken.contains(color)) {
+            Some(color) => *color,
+            None => PLAYER_COLORS[rand::random::<usize>() % PLAYER_COLORS.len()],
+        }
+    }
+
+    pub fn add_player(&mut self, uuid: String, token: String, player_name: String) {
+        let position = self.spawn_position();
+        self.tokens.insert(uuid.clone(), token);
+        let color = self.pick_color();
+        if self.host.is_none() {
+            self.host = Some(uuid.clone());
+        }
+        self.touch_player(&uuid);
+        self.last_activity = Instant::now();
+        self.last_updates.insert(uuid.clone(), self.last_activity);
         self.gamestate.players.insert(
             uuid,
             Player {
                 ready: false,
                 name: player_name,
-                position: (
-                    coords.0 as f32 * 256. + 128.,
-                    coords.1 as f32 * 256. + 128.,
-                ),
+                position,
+                keys: 0,
+                inventory: vec![],
+                state: PlayerState::Alive,
+                color,
             },
         );
     }
 
+    /// Only real keys count, not curses everyone was told were keys.
+    pub fn exit_open(&self) -> bool {
+        self.gamestate.keys >= self.gamestate.settings.keys
+    }
+
+    pub fn is_walkable(&self, position: (f32, f32), movement: (f32, f32)) -> bool {
+        if self.gamestate.map.blocks(position, movement) {
+            return false;
+        }
+        !self.gamestate.map.is_exit(position) || self.exit_open()
+    }
+
+    /// Walks from `from` towards `to`, stopping at the first wall or locked exit on the way unless
+    /// it's a `ghost`, and never further than `max_distance`.
+    /// Jumps longer than `MAX_MOVE_DISTANCE` are rejected outright.
+    pub fn validate_move(&self, from: (f32, f32), to: (f32, f32), max_distance: f32, ghost: bool) -> (f32, f32) {
+        let delta = (to.0 - from.0, to.1 - from.1);
+        let distance = (delta.0 * delta.0 + delta.1 * delta.1).sqrt();
+        if distance == 0. {
+            return to;
+        }
+        if distance > MAX_MOVE_DISTANCE {
+            return from;
+        }
+        let scale = (max_distance / distance).min(1.);
+        let steps = (distance * scale / (TILE_SIZE / 2.)).ceil() as i32;
+        let mut position = from;
+        for step in 1..=steps {
+            let progress = scale * step as f32 / steps as f32;
+            let next = (from.0 + delta.0 * progress, from.1 + delta.1 * progress);
+            if !ghost && !self.is_walkable(next, delta) {
+                break;
+            }
+            position = next;
+        }
+        position
+    }
+
+    pub fn remove_player(&mut self, player_id: &String) -> Option<Player> {
+        let mut player = self.gamestate.players.remove(player_id)?;
+        // The others still need those keys to open the exit
+        self.scatter_keys(room_coord(player.position), player.keys);
+        player.keys = 0;
+        self.last_updates.remove(player_id);
+        self.move_allowances.remove(player_id);
+        self.corrections.remove(player_id);
+        self.player_sequences.remove(player_id);
+        self.resync.remove(player_id);
+        self.count_keys();
+        if self.host.as_ref() == Some(player_id) {
+            self.host = self.gamestate.players.keys().next().cloned();
+        }
+        // Deltas can't express a removal, make everyone resynchronize
+        self.snapshot_sequence = self.next_sequence();
+        Some(player)
+    }
+
+    /// Removes a player who stopped answering but keeps them around so they can rejoin.
+    pub fn disconnect_player(&mut self, player_id: &String) -> Option<Player> {
+        let player = self.remove_player(player_id)?;
+        self.departed.insert(player_id.clone(), player.clone());
+        Some(player)
+    }
+
+    pub fn rejoin_player(&mut self, player_id: &String) -> Option<RejoinGameResponse> {
+        if let Some(player) = self.departed.remove(player_id) {
+            self.add_message(format!("{} has rejoined the game", player.name));
+            self.gamestate.players.insert(player_id.clone(), player);
+            self.touch_player(player_id);
+            self.count_keys();
+            if self.host.is_none() {
+                self.host = Some(player_id.clone());
+            }
+        }
+        let position = self.gamestate.players.get(player_id)?.position;
+        self.last_activity = Instant::now();
+        self.last_updates.insert(player_id.clone(), self.last_activity);
+        let explored_rooms = match self.explored.get(player_id) {
+            Some(explored) => explored.keys().cloned().collect(),
+            None => vec![],
+        };
+        Some(RejoinGameResponse {
+            position,
+            explored_rooms,
+            state: self.response(player_id, 0),
+        })
+    }
+
+    /// Ghosts drift through rooms without exploring them.
+    pub fn explore(&mut self, player_id: &String, rooms: Vec<(i32, i32)>) {
+        if self.is_ghost(player_id) {
+            return;
+        }
+        for room in rooms {
+            if self.gamestate.map.room(room.0, room.1).is_none() {
+                continue;
+            }
+            if self.explored.get(player_id).map_or(false, |explored| explored.contains_key(&room)) {
+                continue;
+            }
+            let sequence = self.next_sequence();
+            self.explored.entry(player_id.clone()).or_insert_with(HashMap::new).insert(room, sequence);
+            self.explored_sequences.entry(room).or_insert(sequence);
+        }
+    }
+
+    pub fn timed_out_players(&self, now: Instant) -> Vec<String> {
+        self.last_updates
+            .iter()
+            .filter(|(_, last_update)| now - **last_update > PLAYER_TIMEOUT)
+            .map(|(player_id, _)| player_id.clone())
+            .collect()
+    }
+
     pub fn update_player(&mut self, player_id: &String, position: (f32, f32), ready: bool) -> Option<()> {
+        let now = Instant::now();
+        self.last_activity = now;
+        self.last_updates.insert(player_id.clone(), now);
+        let current = self.gamestate.players.get(player_id)?.position;
+        let ghost = self.is_ghost(player_id);
+        let validated = if self.gamestate.phase == Phase::Round {
+            // Time adds to the distance a player may cover and moving uses it up, so positions
+            // that arrive bunched together still get the time they took on the way
+            let interval = self.gamestate.settings.player_speed * SQRT_2;
+            let allowance = match self.move_allowances.get(player_id) {
+                Some((topped_up, allowance)) => {
+                    let ticks = (now - *topped_up).as_millis() as f32 / UPDATE_INTERVAL as f32;
+                    (allowance + interval * ticks).min(interval * MAX_MOVE_ALLOWANCE)
+                }
+                None => interval,
+            };
+            let validated = self.validate_move(current, position, allowance, ghost);
+            let left = (allowance - distance(current, validated)).max(0.);
+            self.move_allowances.insert(player_id.clone(), (now, left));
+            validated
+        } else {
+            self.move_allowances.remove(player_id);
+            current
+        };
         let player = self.gamestate.players.get_mut(player_id)?;
-        player.position = position;
+        let changed = player.position != validated || player.ready != ready;
+        player.position = validated;
         player.ready = ready;
+        if changed {
+            self.touch_player(player_id);
+        }
+        if validated == position {
+            self.corrections.remove(player_id);
+        } else {
+            self.corrections.insert(player_id.clone(), validated);
+        }
         Some(())
     }
 
@@ -139,53 +1023,398 @@ impl ServerState {
         }
     }
 
-    pub fn new_game(&mut self, _public: bool) -> String {
+    pub fn new_game(&mut self, public: bool, seed: u64, settings: GameSettings) -> String {
         // let uuid = "yes".to_string();//Uuid::new_v4().to_string();
         let uuid = Uuid::new_v4().to_string()[..6].to_string();
-        self.games.entry(uuid.clone()).or_insert(ServerGamestate::new());
+        self.games.entry(uuid.clone()).or_insert(ServerGamestate::new(public, seed, settings));
         uuid
     }
 
-    pub fn join_game(&mut self, game_id: String, player_name: String) -> Option<(String, (f32, f32))> {
+    pub fn leave_game(&mut self, game_id: &String, player_id: &String, token: &String) -> Option<()> {
+        let game = self.games.get_mut(game_id)?;
+        game.authorize(player_id, token)?;
+        let player = game.forget_player(player_id)?;
+        game.add_message(format!("{} has left the game", player.name));
+        Some(())
+    }
+
+    pub fn rejoin_game(&mut self, game_id: &String, player_id: &String, token: &String) -> Option<RejoinGameResponse> {
+        let game = self.games.get_mut(game_id)?;
+        game.authorize(player_id, token)?;
+        game.rejoin_player(player_id)
+    }
+
+    /// Removes players we haven't heard from in a while, then games that stayed empty.
+    pub fn cleanup(&mut self) {
+        let now = Instant::now();
+        for game in self.games.values_mut() {
+            for player_id in game.timed_out_players(now) {
+                if let Some(player) = game.disconnect_player(&player_id) {
+                    game.add_message(format!("{} has timed out", player.name));
+                }
+            }
+        }
+        self.games.retain(|_, game| {
+            !game.gamestate.players.is_empty() || now - game.last_activity < EMPTY_GAME_TIMEOUT
+        });
+    }
+
+    /// Public games that can still be played, the ones still in their lobby first.
+    pub fn public_games(&self) -> Vec<GameSummary> {
+        let mut games: Vec<GameSummary> = self
+            .games
+            .iter()
+            .filter(|(_, game)| game.public && game.gamestate.phase != Phase::End)
+            .map(|(game_id, game)| GameSummary {
+                game_id: game_id.clone(),
+                players: game.gamestate.players.len(),
+                started: game.gamestate.started,
+                round: game.gamestate.round,
+            })
+            .collect();
+        games.sort_by(|a, b| (a.started, &a.game_id).cmp(&(b.started, &b.game_id)));
+        games
+    }
+
+    /// Returns the new player's id, their token and where they spawned.
+    pub fn join_game(&mut self, game_id: String, player_name: String) -> Option<(String, String, (f32, f32))> {
         let game = self.games.get_mut(&game_id)?;
         let uuid = Uuid::new_v4().to_string();
-        game.add_player(uuid.clone(), player_name);
-        Some((uuid.clone(), game.gamestate.players.get(&uuid).unwrap().position))
-    }
-
-    pub fn update(
-        &mut self,
-        game_id: String,
-        player_id: String,
-        position: (f32, f32),
-        new_rooms: Vec<(i32, i32)>,
-        cleared_rooms: Vec<(i32, i32)>,
-        ready: bool,
-        end: bool,
-    ) -> Option<&Gamestate> {
-        let game = self.games.get_mut(&game_id)?;
-        if end {
-            game.next_round();
-            game.gamestate.messages.push(Message::new(format!("{} has found the exit, a new round is starting!", game.gamestate.players.get(&player_id).unwrap().name)));
-            return Some(&game.gamestate);
+        let token = Uuid::new_v4().to_string();
+        game.add_player(uuid.clone(), token.clone(), player_name);
+        Some((uuid.clone(), token, game.gamestate.players.get(&uuid).unwrap().position))
+    }
+
+    pub fn update(&mut self, request: UpdateRequest) -> Option<()> {
+        let player_id = &request.player_id;
+        let game = self.games.get_mut(&request.game_id)?;
+        game.authorize(player_id, &request.token)?;
+        game.update_player(player_id, request.position, request.ready)?;
+        if let Some(settings) = request.settings {
+            game.set_settings(player_id, settings);
+        }
+        if let Some(kicked) = request.kick {
+            game.kick(player_id, &kicked);
         }
-        game.update_player(&player_id, position, ready)?;
-        if game.all_players_ready() {
-           game.gamestate.started = true;
+        game.tick(Instant::now());
+        let now = game.now();
+        game.effects.expire(now);
+        game.explore(player_id, request.new_rooms);
+        if game.gamestate.phase != Phase::Round {
+            return Some(());
         }
-        for room_pos in cleared_rooms {
-            match game.gamestate.map.room(room_pos.0, room_pos.1)?.item {
+        if request.marker {
+            game.place_marker(player_id);
+        }
+        // Ghosts can't open doors, take exits or pick anything up
+        if game.is_ghost(player_id) {
+            return Some(());
+        }
+        if let Some(room) = request.fake_exit {
+            game.spawn_fake_exit(player_id, room);
+        }
+        if let Some((room, direction)) = request.unlock {
+            game.unlock(player_id, room, &direction);
+        }
+        if request.drop_key {
+            game.drop_key(player_id);
+        }
+        if let Some(teammate) = request.give_key {
+            game.give_key(player_id, &teammate);
+        }
+        if let Some((slot, target)) = request.use_item {
+            game.use_item(player_id, slot, target);
+        }
+        if let Some((slot, teammate)) = request.give_item {
+            game.give_item(player_id, slot, &teammate);
+        }
+        let position = game.gamestate.players.get(player_id)?.position;
+        if request.end && game.gamestate.map.is_exit(position) && game.exit_open() {
+            let name = game.gamestate.players.get(player_id).unwrap().name.clone();
+            let coord = room_coord(position);
+            if !game.gamestate.map.room(coord.0, coord.1)?.is_fake_exit() {
+                game.end_round(Role::Explorer, name);
+                return Some(());
+            }
+            // The traitor stepping on their own fake exit gives nothing away
+            if game.role(player_id) != Role::Traitor {
+                game.end_round(Role::Traitor, name);
+                return Some(());
+            }
+        }
+        for room_pos in request.cleared_rooms {
+            if room_coord(position) != room_pos {
+                continue;
+            }
+            let name = game.gamestate.players.get(player_id).unwrap().name.clone();
+            match game.gamestate.map.room(room_pos.0, room_pos.1)?.item.clone() {
                 Some((Item::Key, _)) => {
-                    game.gamestate.keys += 1;
-                    game.gamestate.messages.push(Message::new(format!("{} has picked up a key!", game.gamestate.players.get(&player_id).unwrap().name)))
+                    game.gamestate.players.get_mut(player_id)?.keys += 1;
+                    game.count_keys();
+                    game.touch_player(player_id);
+                    game.add_message(format!("{} has picked up a key!", name))
+                },
+                Some((Item::Bad, _)) => {
+                    game.reveal_curse(player_id, &Item::Bad);
+                    game.apply_item(player_id, Item::Bad);
                 },
-                Some(_) => {
-                    game.gamestate.messages.push(Message::new(format!("{} has been cursed!", game.gamestate.players.get(&player_id).unwrap().name)))
+                Some((item, _)) => {
+                    game.reveal_curse(player_id, &item);
+                    game.gamestate.players.get_mut(player_id)?.inventory.push(item);
+                    game.touch_player(player_id);
                 },
-                None => {}
+                None => continue,
             }
             game.gamestate.map.mut_room(room_pos.0, room_pos.1)?.item = None;
+            game.touch_room(room_pos);
+        }
+        Some(())
+    }
+
+    pub fn state(&mut self, game_id: &String, player_id: &String, ack: u64) -> Option<UpdateResponse> {
+        let game = self.games.get_mut(game_id)?;
+        game.gamestate.players.get(player_id)?;
+        Some(game.response(player_id, ack))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lycan::shared::gamestate::Map;
+
+    /// A game on a row of three rooms, the exit on the left, with `players` joined under their own names.
+    fn game(players: &[&str]) -> ServerGamestate {
+        let mut game = ServerGamestate::new(false, 1, GameSettings::default());
+        game.gamestate.map = Map::from_rooms(vec![Room::exit((0, 0)), Room::basic((1, 0)), Room::basic((2, 0))]);
+        for player in players {
+            game.add_player(player.to_string(), format!("{} token", player), player.to_string());
+        }
+        game
+    }
+
+    fn start(game: &mut ServerGamestate) {
+        game.gamestate.started = true;
+        game.set_phase(Phase::Round, None);
+    }
+
+    fn place(game: &mut ServerGamestate, player_id: &str, position: (f32, f32)) {
+        game.gamestate.players.get_mut(player_id).unwrap().position = position;
+    }
+
+    fn id(player_id: &str) -> String {
+        player_id.to_string()
+    }
+
+    #[test]
+    fn responses_hide_what_other_players_keep_to_themselves() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        place(&mut game, "a", (384., 128.));
+        place(&mut game, "b", (400., 128.));
+        game.gamestate.players.get_mut("b").unwrap().inventory.push(Item::Spin);
+        game.make_traitor(&id("b"));
+        game.explore(&id("a"), vec![(1, 0)]);
+
+        let response = game.response(&id("a"), 0);
+        assert!(response.players["b"].inventory.is_empty());
+        assert_eq!(response.role, Role::Explorer);
+        let response = game.response(&id("b"), 0);
+        assert_eq!(response.players["b"].inventory, vec![Item::Spin]);
+        assert_eq!(response.role, Role::Traitor);
+    }
+
+    #[test]
+    fn players_out_of_sight_are_hidden() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        place(&mut game, "a", (384., 128.));
+        place(&mut game, "b", (640., 128.));
+        game.explore(&id("a"), vec![(1, 0)]);
+
+        let response = game.response(&id("a"), 0);
+        assert!(!response.players.contains_key("b"));
+        assert_eq!(response.hidden_players, vec![id("b")]);
+    }
+
+    #[test]
+    fn tokens_prove_who_is_asking() {
+        let game = game(&["a"]);
+        assert!(game.authorize(&id("a"), &id("a token")).is_some());
+        assert!(game.authorize(&id("a"), &id("b token")).is_none());
+        assert!(game.authorize(&id("b"), &id("a token")).is_none());
+    }
+
+    #[test]
+    fn keys_stay_behind_when_their_holder_leaves() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        place(&mut game, "b", (384., 128.));
+        game.gamestate.players.get_mut("b").unwrap().keys = 2;
+        game.count_keys();
+
+        let player = game.disconnect_player(&id("b")).unwrap();
+        assert_eq!(player.keys, 0);
+        assert_eq!(game.departed["b"].keys, 0);
+        assert_eq!(game.gamestate.keys, 0);
+        // Never in the exit, one per room
+        for position in &[(1, 0), (2, 0)] {
+            let room = game.gamestate.map.room(position.0, position.1).unwrap();
+            assert!(matches!(room.item, Some((Item::Key, _))));
         }
-        Some(&game.gamestate)
+        assert!(game.gamestate.map.room(0, 0).unwrap().item.is_none());
+    }
+
+    #[test]
+    fn disguised_keys_follow_their_curse() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        game.gamestate.settings.keys = 1;
+        place(&mut game, "a", (384., 128.));
+        place(&mut game, "b", (400., 128.));
+        game.explore(&id("b"), vec![(1, 0)]);
+        game.reveal_curse(&id("a"), &Item::Spin);
+        game.gamestate.players.get_mut("a").unwrap().inventory.push(Item::Spin);
+
+        let response = game.response(&id("b"), 0);
+        assert_eq!(response.keys, 1);
+        assert_eq!(response.players["a"].keys, 1);
+        assert!(!response.exit_open);
+
+        game.give_item(&id("a"), 0, &id("b"));
+        let response = game.response(&id("b"), 0);
+        assert_eq!(response.keys, 1);
+        assert_eq!(response.players["a"].keys, 0);
+        assert_eq!(response.players["b"].keys, 1);
+
+        game.use_item(&id("b"), 0, None);
+        assert_eq!(game.response(&id("b"), 0).keys, 0);
+    }
+
+    #[test]
+    fn moves_through_walls_are_clamped() {
+        let game = game(&[]);
+        // Row 4 of the basic room has its west wall on the second tile
+        let from = (256. + 128., 72.);
+        let moved = game.validate_move(from, (256. + 8., 72.), f32::MAX, false);
+        assert!(tile_coord(moved).0 >= 2);
+        assert_eq!(game.validate_move(from, (256. + 8., 72.), f32::MAX, true), (256. + 8., 72.));
+        // Too far to be anything but a cheat
+        assert_eq!(game.validate_move(from, (1000., 72.), f32::MAX, false), from);
+    }
+
+    #[test]
+    fn moves_are_limited_to_the_allowance() {
+        let mut game = game(&["a"]);
+        start(&mut game);
+        place(&mut game, "a", (384., 128.));
+        game.update_player(&id("a"), (384. + 64., 128.), false);
+        let position = game.gamestate.players["a"].position;
+        assert!(position.0 < 384. + 64.);
+        assert_eq!(game.corrections.get("a"), Some(&position));
+    }
+
+    #[test]
+    fn deltas_only_carry_what_changed() {
+        let mut game = game(&["a"]);
+        start(&mut game);
+        game.explore(&id("a"), vec![(1, 0), (2, 0)]);
+        let snapshot = game.response(&id("a"), 0);
+        assert!(snapshot.snapshot);
+        assert_eq!(snapshot.rooms.len(), 2);
+
+        game.touch_room((2, 0));
+        let delta = game.response(&id("a"), snapshot.sequence);
+        assert!(!delta.snapshot);
+        assert_eq!(delta.since, snapshot.sequence);
+        let rooms: Vec<(i32, i32)> = delta.rooms.iter().map(|room| room.position).collect();
+        assert_eq!(rooms, vec![(2, 0)]);
+    }
+
+    #[test]
+    fn new_rounds_force_a_snapshot() {
+        let mut game = game(&["a"]);
+        start(&mut game);
+        let ack = game.response(&id("a"), 0).sequence;
+        assert!(!game.response(&id("a"), ack).snapshot);
+        game.next_round();
+        assert!(game.response(&id("a"), ack).snapshot);
+    }
+
+    #[test]
+    fn new_rounds_start_unexplored() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        game.make_traitor(&id("b"));
+        game.explore(&id("a"), vec![(1, 0), (2, 0)]);
+        assert_eq!(game.response(&id("b"), 0).revealed_rooms.len(), 2);
+
+        game.next_round();
+        assert!(game.response(&id("a"), 0).rooms.is_empty());
+        let response = game.response(&id("b"), 0);
+        assert!(response.rooms.is_empty());
+        assert!(response.revealed_rooms.is_empty());
+    }
+
+    #[test]
+    fn fake_exits_only_fool_explorers() {
+        let mut game = game(&["a", "b"]);
+        start(&mut game);
+        place(&mut game, "b", (384., 128.));
+        assert!(game.spawn_fake_exit(&id("b"), (2, 0)).is_none());
+        game.make_traitor(&id("b"));
+        // Only next to the traitor
+        assert!(game.spawn_fake_exit(&id("b"), (3, 0)).is_none());
+        assert!(game.spawn_fake_exit(&id("b"), (2, 0)).is_some());
+        assert!(game.spawn_fake_exit(&id("b"), (1, 0)).is_none());
+
+        let room = game.gamestate.map.room(2, 0).unwrap();
+        assert!(matches!(game.disguise(&id("a"), room).room_type, RoomType::Exit));
+        assert!(game.disguise(&id("b"), room).is_fake_exit());
+    }
+
+    #[test]
+    fn the_match_moves_through_its_phases() {
+        let mut game = game(&["a", "b"]);
+        game.gamestate.settings.rounds = 1;
+        let now = Instant::now();
+        game.tick(now);
+        assert_eq!(game.gamestate.phase, Phase::Lobby);
+
+        game.gamestate.players.values_mut().for_each(|player| player.ready = true);
+        game.tick(now);
+        assert_eq!(game.gamestate.phase, Phase::Countdown);
+        // Cancelling before the first round goes back to the lobby
+        game.gamestate.players.get_mut("b").unwrap().ready = false;
+        game.tick(now);
+        assert_eq!(game.gamestate.phase, Phase::Lobby);
+        game.gamestate.players.get_mut("b").unwrap().ready = true;
+        game.tick(now);
+        game.tick(Instant::now() + COUNTDOWN_DURATION);
+        assert_eq!(game.gamestate.phase, Phase::Round);
+        assert!(game.gamestate.started);
+
+        game.end_round(Role::Explorer, id("a"));
+        assert_eq!(game.gamestate.phase, Phase::Summary);
+        game.tick(Instant::now() + SUMMARY_DURATION);
+        assert_eq!(game.gamestate.phase, Phase::End);
+    }
+
+    #[test]
+    fn summaries_lead_to_the_next_round_until_the_match_is_won() {
+        let mut game = game(&["a"]);
+        game.gamestate.settings.rounds = 2;
+        start(&mut game);
+        game.end_round(Role::Explorer, id("a"));
+        game.tick(Instant::now() + SUMMARY_DURATION);
+        assert_eq!(game.gamestate.phase, Phase::Countdown);
+        assert_eq!(game.gamestate.round, 2);
+        game.tick(Instant::now() + COUNTDOWN_DURATION);
+        assert_eq!(game.gamestate.phase, Phase::Round);
+
+        // The traitor only needs one
+        game.end_round(Role::Traitor, id("a"));
+        game.tick(Instant::now() + SUMMARY_DURATION);
+        assert_eq!(game.gamestate.phase, Phase::End);
     }
 }
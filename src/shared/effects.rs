@@ -0,0 +1,287 @@
+use crate::shared::room::Item;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const REVERSE_DURATION: u64 = 20_000;
+
+/// Who an item's effect lands on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// The player the item was used on, whoever holds it unless they aimed it at a teammate.
+    User,
+    All,
+    /// One player in the game picked at random, possibly the one using it.
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectDuration {
+    /// Happens once and is gone.
+    Instant,
+    /// Lasts this many milliseconds.
+    Timed(u64),
+    /// Lasts until the end of the round.
+    Round,
+}
+
+/// What happens when an effect lands on someone who already has it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stacking {
+    /// Adds a stack and restarts the timer.
+    Stack,
+    /// Restarts the timer.
+    Refresh,
+    /// Does nothing.
+    Ignore,
+}
+
+/// Who learns what a cursed key really was when it's picked up. Everyone else is told a key was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reveal {
+    Nobody,
+    Picker,
+    Everyone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectRule {
+    pub duration: EffectDuration,
+    pub target: Target,
+    pub stacking: Stacking,
+    /// Confusion curses don't work on the traitor.
+    pub confusion: bool,
+    pub reveal: Reveal,
+}
+
+impl EffectRule {
+    /// The effect an item has when used, keys don't do anything but open the exit.
+    pub fn of(item: &Item) -> Option<EffectRule> {
+        match item {
+            Item::Key => None,
+            Item::Clear => Some(EffectRule {
+                duration: EffectDuration::Instant,
+                target: Target::User,
+                stacking: Stacking::Ignore,
+                confusion: true,
+                reveal: Reveal::Picker,
+            }),
+            Item::Spin => Some(EffectRule {
+                duration: EffectDuration::Round,
+                target: Target::User,
+                stacking: Stacking::Stack,
+                confusion: true,
+                reveal: Reveal::Picker,
+            }),
+            Item::Reverse => Some(EffectRule {
+                duration: EffectDuration::Timed(REVERSE_DURATION),
+                target: Target::User,
+                stacking: Stacking::Refresh,
+                confusion: true,
+                reveal: Reveal::Picker,
+            }),
+            Item::Teleport => Some(EffectRule {
+                duration: EffectDuration::Instant,
+                target: Target::User,
+                stacking: Stacking::Ignore,
+                confusion: true,
+                reveal: Reveal::Everyone,
+            }),
+            Item::Bad => Some(EffectRule {
+                duration: EffectDuration::Instant,
+                target: Target::User,
+                stacking: Stacking::Ignore,
+                confusion: false,
+                reveal: Reveal::Nobody,
+            }),
+        }
+    }
+
+    /// Who the effect lands on when `user` uses it, out of `players`. `roll` is any random
+    /// number, it picks the player for `Target::Random`.
+    pub fn targets(&self, user: &str, players: &[String], roll: usize) -> Vec<String> {
+        match self.target {
+            Target::User => vec![user.to_string()],
+            Target::All => players.to_vec(),
+            Target::Random if players.is_empty() => vec![],
+            Target::Random => vec![players[roll % players.len()].clone()],
+        }
+    }
+}
+
+/// An effect active on a player, as sent to them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Effect {
+    pub item: Item,
+    pub stacks: u32,
+    /// Milliseconds left, `None` when it lasts until the end of the round.
+    pub remaining: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct ActiveEffect {
+    item: Item,
+    stacks: u32,
+    expires: Option<u64>,
+}
+
+/// Effects active on each player. Times are in milliseconds from any fixed point, as long as
+/// it's always the same one.
+#[derive(Debug, Default)]
+pub struct Effects {
+    active: HashMap<String, Vec<ActiveEffect>>,
+}
+
+impl Effects {
+    /// Puts the effect of `item` on `player_id`, returns whether it did anything.
+    pub fn apply(&mut self, player_id: &str, item: &Item, now: u64) -> bool {
+        let rule = match EffectRule::of(item) {
+            Some(rule) => rule,
+            None => return false,
+        };
+        let expires = match rule.duration {
+            EffectDuration::Instant => return true,
+            EffectDuration::Timed(duration) => Some(now + duration),
+            EffectDuration::Round => None,
+        };
+        let effects = self.active.entry(player_id.to_string()).or_insert_with(Vec::new);
+        effects.retain(|effect| effect.expires.map_or(true, |expires| expires > now));
+        match effects.iter_mut().find(|effect| &effect.item == item) {
+            Some(effect) => match rule.stacking {
+                Stacking::Stack => {
+                    effect.stacks += 1;
+                    effect.expires = expires;
+                }
+                Stacking::Refresh => effect.expires = expires,
+                Stacking::Ignore => return false,
+            },
+            None => effects.push(ActiveEffect {
+                item: item.clone(),
+                stacks: 1,
+                expires,
+            }),
+        }
+        true
+    }
+
+    pub fn active(&self, player_id: &str, now: u64) -> Vec<Effect> {
+        self.active
+            .get(player_id)
+            .into_iter()
+            .flatten()
+            .filter(|effect| effect.expires.map_or(true, |expires| expires > now))
+            .map(|effect| Effect {
+                item: effect.item.clone(),
+                stacks: effect.stacks,
+                remaining: effect.expires.map(|expires| expires - now),
+            })
+            .collect()
+    }
+
+    /// How many stacks of `item` are on `player_id`, 0 when it isn't active.
+    pub fn stacks(&self, player_id: &str, item: &Item, now: u64) -> u32 {
+        self.active(player_id, now)
+            .iter()
+            .filter(|effect| &effect.item == item)
+            .map(|effect| effect.stacks)
+            .sum()
+    }
+
+    /// Forgets effects that ran out.
+    pub fn expire(&mut self, now: u64) {
+        for effects in self.active.values_mut() {
+            effects.retain(|effect| effect.expires.map_or(true, |expires| expires > now));
+        }
+        self.active.retain(|_, effects| !effects.is_empty());
+    }
+
+    pub fn remove_player(&mut self, player_id: &str) {
+        self.active.remove(player_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(target: Target) -> EffectRule {
+        EffectRule {
+            target,
+            ..EffectRule::of(&Item::Spin).unwrap()
+        }
+    }
+
+    #[test]
+    fn timed_effects_run_out() {
+        let mut effects = Effects::default();
+        assert!(effects.apply("a", &Item::Reverse, 1_000));
+        let active = effects.active("a", 1_000 + REVERSE_DURATION - 1);
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].remaining, Some(1));
+        assert!(effects.active("a", 1_000 + REVERSE_DURATION).is_empty());
+        effects.expire(1_000 + REVERSE_DURATION);
+        assert!(effects.active("a", 0).is_empty());
+    }
+
+    #[test]
+    fn round_effects_last_until_cleared() {
+        let mut effects = Effects::default();
+        effects.apply("a", &Item::Spin, 0);
+        effects.expire(u64::MAX);
+        assert_eq!(effects.active("a", u64::MAX)[0].remaining, None);
+        effects.clear();
+        assert!(effects.active("a", 0).is_empty());
+    }
+
+    #[test]
+    fn stacking_effects_add_up() {
+        let mut effects = Effects::default();
+        assert!(effects.apply("a", &Item::Spin, 0));
+        assert!(effects.apply("a", &Item::Spin, 10));
+        assert_eq!(effects.stacks("a", &Item::Spin, 20), 2);
+        assert_eq!(effects.stacks("a", &Item::Reverse, 20), 0);
+        assert_eq!(effects.stacks("b", &Item::Spin, 20), 0);
+    }
+
+    #[test]
+    fn refreshing_effects_restart_their_timer() {
+        let mut effects = Effects::default();
+        effects.apply("a", &Item::Reverse, 0);
+        assert!(effects.apply("a", &Item::Reverse, 5_000));
+        assert_eq!(effects.stacks("a", &Item::Reverse, 5_000), 1);
+        assert_eq!(effects.active("a", 5_000)[0].remaining, Some(REVERSE_DURATION));
+        assert_eq!(effects.stacks("a", &Item::Reverse, REVERSE_DURATION + 1_000), 1);
+    }
+
+    #[test]
+    fn instant_effects_leave_nothing_behind() {
+        let mut effects = Effects::default();
+        assert!(effects.apply("a", &Item::Teleport, 0));
+        assert!(effects.active("a", 0).is_empty());
+        assert!(!effects.apply("a", &Item::Key, 0));
+    }
+
+    #[test]
+    fn removed_players_lose_their_effects() {
+        let mut effects = Effects::default();
+        effects.apply("a", &Item::Spin, 0);
+        effects.apply("b", &Item::Spin, 0);
+        effects.remove_player("a");
+        assert_eq!(effects.stacks("a", &Item::Spin, 0), 0);
+        assert_eq!(effects.stacks("b", &Item::Spin, 0), 1);
+    }
+
+    #[test]
+    fn targets_follow_the_rule() {
+        let players = vec![String::from("a"), String::from("b"), String::from("c")];
+        assert_eq!(rule(Target::User).targets("b", &players, 0), vec!["b"]);
+        assert_eq!(rule(Target::All).targets("b", &players, 0), players);
+        assert_eq!(rule(Target::Random).targets("b", &players, 4), vec!["b"]);
+        assert_eq!(rule(Target::Random).targets("b", &players, 6), vec!["a"]);
+        assert!(rule(Target::Random).targets("b", &[], 6).is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::shared::map_generator::CaveStyle;
+use crate::shared::room::Item;
+use crate::shared::utils::{KEYS_REQUIRED, PLAYER_SPEED, ROUND_TARGET};
+
+use serde::{Deserialize, Serialize};
+
+pub const MAX_KEYS: u32 = 16;
+pub const MAX_ROUNDS: u32 = 9;
+pub const MIN_MAP_RADIUS: i32 = 3;
+pub const MAX_MAP_RADIUS: i32 = 30;
+/// Enough rooms for every key, curse and locked door to get one of its own.
+pub const MIN_ROOMS: usize = 30;
+pub const MIN_PLAYER_SPEED: f32 = 1.;
+pub const MAX_PLAYER_SPEED: f32 = 6.;
+/// Every curse a cave can hide, in the order the lobby lists them.
+pub const CURSES: [Item; 5] = [Item::Clear, Item::Spin, Item::Reverse, Item::Teleport, Item::Bad];
+
+/// How a game is played, picked when creating it and changed by the host in the lobby.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GameSettings {
+    /// Keys needed to open the exit.
+    pub keys: u32,
+    /// Exits the explorers have to find to win the match.
+    pub rounds: u32,
+    /// Curses hidden among the keys each round, at most one of each.
+    pub curses: Vec<Item>,
+    /// Rooms are at most this many rooms away from the exit on either axis.
+    pub map_radius: i32,
+    pub rooms: usize,
+    pub style: CaveStyle,
+    /// Pixels per `UPDATE_INTERVAL`.
+    pub player_speed: f32,
+}
+
+impl GameSettings {
+    /// Keeps everything within what a cave can hold.
+    pub fn clamped(self) -> GameSettings {
+        let map_radius = self.map_radius.clamp(MIN_MAP_RADIUS, MAX_MAP_RADIUS);
+        let side = (map_radius * 2 + 1) as usize;
+        GameSettings {
+            keys: self.keys.clamp(1, MAX_KEYS),
+            rounds: self.rounds.clamp(1, MAX_ROUNDS),
+            curses: CURSES.iter().filter(|curse| self.curses.contains(curse)).cloned().collect(),
+            map_radius,
+            rooms: self.rooms.clamp(MIN_ROOMS, side * side),
+            style: self.style,
+            player_speed: self.player_speed.clamp(MIN_PLAYER_SPEED, MAX_PLAYER_SPEED),
+        }
+    }
+
+    /// Whether both give the same cave, the rest can change without making it again.
+    pub fn same_map(&self, other: &GameSettings) -> bool {
+        self.keys == other.keys
+            && self.curses == other.curses
+            && self.map_radius == other.map_radius
+            && self.rooms == other.rooms
+            && self.style == other.style
+    }
+
+    /// Adds the curse if it was missing, removes it otherwise.
+    pub fn toggle_curse(&mut self, curse: &Item) {
+        if self.curses.contains(curse) {
+            self.curses.retain(|item| item != curse);
+        } else {
+            self.curses.push(curse.clone());
+        }
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            keys: KEYS_REQUIRED,
+            rounds: ROUND_TARGET,
+            curses: CURSES.to_vec(),
+            map_radius: 17,
+            rooms: 200,
+            style: CaveStyle::Tree,
+            player_speed: PLAYER_SPEED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_cave_settings_change_the_map() {
+        let settings = GameSettings::default();
+        assert!(settings.same_map(&GameSettings { rounds: 5, player_speed: 4., ..settings.clone() }));
+        assert!(!settings.same_map(&GameSettings { keys: 3, ..settings.clone() }));
+        assert!(!settings.same_map(&GameSettings { rooms: 100, ..settings.clone() }));
+        assert!(!settings.same_map(&GameSettings { style: CaveStyle::Loops, ..settings.clone() }));
+    }
+}
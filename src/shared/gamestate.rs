@@ -1,7 +1,8 @@
-use crate::shared::room::Room;
-use crate::shared::utils::Direction;
+use crate::shared::game_settings::GameSettings;
+use crate::shared::room::{Door, Item, Room};
+use crate::shared::utils::{room_coord, tile_coord, Direction, DIRECTIONS};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
@@ -14,16 +15,60 @@ pub struct Gamestate {
     pub keys: u32,
     pub messages: Vec<Message>,
     pub round: u32,
+    pub phase: Phase,
+    pub settings: GameSettings,
+    pub results: Vec<RoundResult>,
+}
+
+/// Where a match is at. Players wait in the lobby, then each round starts with a countdown and
+/// ends with a summary, until the explorers found `settings.rounds` exits or the traitor won one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Lobby,
+    Countdown,
+    Round,
+    Summary,
+    End,
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Lobby
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoundResult {
+    pub round: u32,
+    pub winner: Role,
+    /// Name of whoever took the exit that ended the round.
+    pub player: String,
+    /// Name of the traitor, if there was one.
+    pub traitor: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub text: String,
+    /// Only this player gets the message, it is never sent over the wire.
+    #[serde(skip)]
+    pub recipient: Option<String>,
 }
 
 impl Message {
     pub fn new(text: String) -> Message {
-        Message{text}
+        Message{text, recipient: None}
+    }
+
+    pub fn private(text: String, recipient: String) -> Message {
+        Message{text, recipient: Some(recipient)}
+    }
+
+    pub fn visible_to(&self, player_id: &String) -> bool {
+        match &self.recipient {
+            Some(recipient) => recipient == player_id,
+            None => true,
+        }
     }
 }
 
@@ -35,45 +80,15 @@ impl Gamestate {
         self.messages = vec![];
     }
 
+    /// Puts a placeholder where a room is known to be until the server sends it. Its neighbours
+    /// are left alone, only the server knows which of their doors are open.
     pub fn add_room(&mut self, position: (i32, i32)) -> bool {
         if !self.map.room(position.0, position.1).is_none() {
             return false;
         }
         self.map.add_room(position, Room::basic(position));
-        self.remove_doors(position);
         true
     }
-
-    pub fn remove_doors(&mut self, position: (i32, i32)) {
-        for room_pos in [
-            (position.0, position.1 + 1),
-            (position.0, position.1 - 1),
-            (position.0 - 1, position.1),
-            (position.0 + 1, position.1),
-        ]
-        .iter()
-        {
-            if self.map.room(room_pos.0, room_pos.1).is_none()
-                && self.map.room_degree(*room_pos) > 1
-            {
-                for (room_pos, direction) in [
-                    ((room_pos.0, room_pos.1 + 1), Direction::Down),
-                    ((room_pos.0, room_pos.1 - 1), Direction::Up),
-                    ((room_pos.0 - 1, room_pos.1), Direction::Right),
-                    ((room_pos.0 + 1, room_pos.1), Direction::Left),
-                ]
-                .iter()
-                {
-                    match self.map.room_mut(room_pos.0, room_pos.1) {
-                        Some(room) => {
-                            *room.doors.get_mut(&direction.to_string()).unwrap() = false;
-                        }
-                        _ => (),
-                    }
-                }
-            }
-        }
-    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,6 +97,20 @@ pub struct Map {
 }
 
 impl Map {
+    pub fn from_rooms(rooms: Vec<Room>) -> Map {
+        let mut map = Map {
+            rooms: HashMap::new(),
+        };
+        for room in rooms {
+            map.add_room(room.position, room);
+        }
+        map
+    }
+
+    pub fn all_rooms(&self) -> Vec<&Room> {
+        self.rooms.values().flat_map(|row| row.values()).collect()
+    }
+
     pub fn room(&self, x: i32, y: i32) -> Option<&Room> {
         self.rooms.get(&x)?.get(&y)
     }
@@ -123,6 +152,106 @@ impl Map {
         }
         degree
     }
+
+    /// Whether the doors on both sides let players walk from the room at `position` towards `direction`.
+    /// A one-way door only lets players out of its own room. With `unlocked`, locked doors count
+    /// as open since someone with a key can get through.
+    pub fn is_open(&self, position: (i32, i32), direction: &Direction, unlocked: bool) -> bool {
+        let offset = direction.offset();
+        let neighbour = (position.0 + offset.0, position.1 + offset.1);
+        let (from, to) = match (self.room(position.0, position.1), self.room(neighbour.0, neighbour.1)) {
+            (Some(room), Some(other)) => (room.doors.get(direction), other.doors.get(&direction.opposite())),
+            _ => return false,
+        };
+        match (from, to) {
+            (Door::Locked, Door::Locked) => unlocked,
+            (from, to) => from.is_passable() && to == Door::Open,
+        }
+    }
+
+    /// Rooms players can walk to from `from`, without unlocking any door.
+    pub fn reachable(&self, from: (i32, i32)) -> HashSet<(i32, i32)> {
+        self.search(from, false, false)
+    }
+
+    /// Rooms players can walk from to reach `to`, which differs from `reachable` with one-way doors.
+    pub fn reaching(&self, to: (i32, i32)) -> HashSet<(i32, i32)> {
+        self.search(to, true, false)
+    }
+
+    fn search(&self, from: (i32, i32), backwards: bool, unlocked: bool) -> HashSet<(i32, i32)> {
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        if self.room(from.0, from.1).is_some() {
+            reached.insert(from);
+            queue.push_back(from);
+        }
+        while let Some(position) = queue.pop_front() {
+            for direction in DIRECTIONS.iter() {
+                let offset = direction.offset();
+                let neighbour = (position.0 + offset.0, position.1 + offset.1);
+                let open = if backwards {
+                    self.is_open(neighbour, &direction.opposite(), unlocked)
+                } else {
+                    self.is_open(position, direction, unlocked)
+                };
+                if open && reached.insert(neighbour) {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Every room can be reached from every other one, walking through open doors
+    /// or unlocking locked ones.
+    pub fn is_connected(&self) -> bool {
+        let rooms = self.all_rooms();
+        match rooms.first() {
+            Some(room) => {
+                self.search(room.position, false, true).len() == rooms.len()
+                    && self.search(room.position, true, true).len() == rooms.len()
+            }
+            None => true,
+        }
+    }
+
+    pub fn is_wall(&self, position: (f32, f32)) -> bool {
+        let coord = room_coord(position);
+        match self.room(coord.0, coord.1) {
+            Some(room) => room.is_wall(tile_coord(position)),
+            None => true,
+        }
+    }
+
+    /// Same as `is_wall`, also stopping players going the wrong way through one-way doors.
+    pub fn blocks(&self, position: (f32, f32), movement: (f32, f32)) -> bool {
+        let coord = room_coord(position);
+        match self.room(coord.0, coord.1) {
+            Some(room) => room.blocks(tile_coord(position), movement),
+            None => true,
+        }
+    }
+
+    /// Unlocks the door on both sides of the passage from `position` towards `direction`.
+    pub fn unlock(&mut self, position: (i32, i32), direction: &Direction) -> bool {
+        let offset = direction.offset();
+        let neighbour = (position.0 + offset.0, position.1 + offset.1);
+        if !self.is_open(position, direction, true) || self.is_open(position, direction, false) {
+            return false;
+        }
+        self.mut_room(position.0, position.1).unwrap().doors.set(direction, Door::Open);
+        self.mut_room(neighbour.0, neighbour.1).unwrap().doors.set(&direction.opposite(), Door::Open);
+        true
+    }
+
+    pub fn is_exit(&self, position: (f32, f32)) -> bool {
+        let coord = room_coord(position);
+        match self.room(coord.0, coord.1) {
+            Some(room) => room.is_exit(tile_coord(position)),
+            None => false,
+        }
+    }
 }
 
 impl Default for Map {
@@ -135,11 +264,56 @@ impl Default for Map {
     }
 }
 
+/// Hidden role of a player, only ever sent to the player it belongs to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Role {
+    Explorer,
+    Traitor,
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Role::Explorer
+    }
+}
+
+/// Colors handed out to players as they join, reused once everyone has one.
+pub const PLAYER_COLORS: [(u8, u8, u8); 8] = [
+    (230, 60, 60),
+    (60, 140, 230),
+    (80, 200, 80),
+    (240, 200, 40),
+    (200, 80, 220),
+    (240, 140, 40),
+    (60, 210, 210),
+    (240, 130, 180),
+];
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Player {
     pub name: String,
     pub position: (f32, f32),
     pub ready: bool,
+    pub keys: u32,
+    /// Only sent to the player it belongs to.
+    pub inventory: Vec<Item>,
+    pub state: PlayerState,
+    /// Picked by the server when joining, kept for the whole game.
+    pub color: (u8, u8, u8),
+}
+
+/// A traitor defeated by the explorers stays in the match as a ghost. Ghosts go through walls,
+/// can't pick anything up and only other ghosts can see them, but they can leave markers.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PlayerState {
+    Alive,
+    Ghost,
+}
+
+impl Default for PlayerState {
+    fn default() -> Self {
+        PlayerState::Alive
+    }
 }
 
 impl Player {
@@ -164,6 +338,9 @@ impl Default for Gamestate {
             keys: 0,
             messages: vec![],
             round: 1,
+            phase: Phase::Lobby,
+            settings: GameSettings::default(),
+            results: vec![],
         }
     }
 }
@@ -173,3 +350,21 @@ pub struct SharedPlayer {
     pub name: String,
     pub position: (f32, f32),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholder_rooms_leave_known_doors_alone() {
+        let mut gamestate = Gamestate::default();
+        gamestate.map.add_room((0, 0), Room::basic((0, 0)));
+        gamestate.map.add_room((1, 0), Room::basic((1, 0)));
+        assert!(gamestate.add_room((1, 1)));
+        assert!(!gamestate.add_room((1, 1)));
+        for direction in DIRECTIONS.iter() {
+            assert_eq!(gamestate.map.room(0, 0).unwrap().doors.get(direction), Door::Open);
+            assert_eq!(gamestate.map.room(1, 0).unwrap().doors.get(direction), Door::Open);
+        }
+    }
+}
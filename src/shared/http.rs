@@ -2,16 +2,38 @@ use std::{collections::HashMap, fmt::Debug};
 
 use serde::{Deserialize, Serialize};
 
-use crate::shared::gamestate::{Gamestate, Map, Player, Message};
+use crate::shared::game_settings::GameSettings;
+use crate::shared::gamestate::{Player, Message, Phase, Role, RoundResult};
+use crate::shared::effects::Effect;
+use crate::shared::room::Room;
+use crate::shared::utils::Direction;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NewGameRequest {
     pub public: bool,
+    /// Picked at random by the server when missing.
+    pub seed: Option<u64>,
+    /// The default settings when missing.
+    pub settings: Option<GameSettings>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NewGameResponse {
     pub game_id: String,
+    pub seed: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub players: usize,
+    pub started: bool,
+    pub round: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameListResponse {
+    pub games: Vec<GameSummary>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,39 +45,92 @@ pub struct JoinGameRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JoinGameResponse {
     pub player_id: String,
+    /// Proves the player is `player_id`. Unlike their id, it's never shown to the other players.
+    pub token: String,
+    pub position: (f32, f32),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejoinGameRequest {
+    pub game_id: String,
+    pub player_id: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejoinGameResponse {
     pub position: (f32, f32),
+    pub explored_rooms: Vec<(i32, i32)>,
+    pub state: UpdateResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaveGameRequest {
+    pub game_id: String,
+    pub player_id: String,
+    pub token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateRequest {
     pub game_id: String,
     pub player_id: String,
+    pub token: String,
     pub position: (f32, f32),
     pub new_rooms: Vec<(i32, i32)>,
     pub cleared_rooms: Vec<(i32, i32)>,
     pub ready: bool,
     pub end: bool,
+    pub fake_exit: Option<(i32, i32)>,
+    /// Locked door the player ran into, to open with one of their keys.
+    pub unlock: Option<((i32, i32), Direction)>,
+    pub drop_key: bool,
+    /// Teammate to hand one of our keys to.
+    pub give_key: Option<String>,
+    /// Inventory slot to use, on a teammate or on ourselves when there is no target.
+    pub use_item: Option<(usize, Option<String>)>,
+    /// Inventory slot to hand to a teammate.
+    pub give_item: Option<(usize, String)>,
+    /// Ghosts leave a marker where they stand.
+    pub marker: bool,
+    /// Only the host can change the settings, in the lobby.
+    pub settings: Option<GameSettings>,
+    /// Only the host can kick players.
+    pub kick: Option<String>,
+    pub ack: u64,
 }
 
+/// State changes between the `since` sequence acknowledged by the client and `sequence`.
+/// When `snapshot` is set, `rooms`, `players` and `revealed_rooms` hold the whole state instead.
+/// Responses are built for one player: they only hold the rooms that player can see,
+/// the players standing in them and the messages meant for them.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateResponse {
+    pub sequence: u64,
+    pub since: u64,
+    pub snapshot: bool,
     pub players: HashMap<String, Player>,
-    pub map: Map,
+    pub hidden_players: Vec<String>,
+    pub rooms: Vec<Room>,
     pub started: bool,
+    /// Keys players are told were found, curses disguised as keys included.
     pub keys: u32,
+    /// Whether the keys really found open the exit, which `keys` can't tell.
+    pub exit_open: bool,
+    pub messages_from: usize,
     pub messages: Vec<Message>,
     pub round: u32,
-}
-
-impl UpdateResponse {
-    pub fn new(gamestate: &Gamestate) -> UpdateResponse {
-        UpdateResponse {
-            started: gamestate.started,
-            players: gamestate.players.clone(),
-            map: gamestate.map.clone(),
-            keys: gamestate.keys,
-            messages: gamestate.messages.clone(),
-            round: gamestate.round,
-        }
-    }
+    pub phase: Phase,
+    /// Milliseconds until the round starts, during the countdown.
+    pub countdown: Option<u64>,
+    pub settings: GameSettings,
+    pub host: Option<String>,
+    pub results: Vec<RoundResult>,
+    pub correction: Option<(f32, f32)>,
+    pub role: Role,
+    pub revealed_rooms: Vec<(i32, i32)>,
+    /// Every effect currently on this player.
+    pub effects: Vec<Effect>,
+    /// Ghost markers in the rooms this player can see.
+    pub markers: Vec<(f32, f32)>,
 }
@@ -0,0 +1,246 @@
+use crate::shared::game_settings::GameSettings;
+use crate::shared::gamestate::Map;
+use crate::shared::room::{Door, Item, Room, RoomType};
+use crate::shared::room_template::ROOM_TEMPLATES;
+use crate::shared::utils::{Direction, DIRECTIONS};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum CaveStyle {
+    /// Branches grow from anywhere, every room is reached by a single path.
+    Tree,
+    /// A tree with extra doors between neighbouring rooms, so there is more than one way around.
+    Loops,
+    /// Long, mostly straight tunnels that rarely branch.
+    Corridors,
+}
+
+impl CaveStyle {
+    /// The style after this one, for cycling through them in the lobby.
+    pub fn next(&self) -> CaveStyle {
+        match self {
+            CaveStyle::Tree => CaveStyle::Loops,
+            CaveStyle::Loops => CaveStyle::Corridors,
+            CaveStyle::Corridors => CaveStyle::Tree,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match self {
+            CaveStyle::Tree => "tree".to_string(),
+            CaveStyle::Loops => "loops".to_string(),
+            CaveStyle::Corridors => "corridors".to_string(),
+        }
+    }
+}
+
+/// Grows a cave around the exit at (0, 0). The same seed and parameters always give the same map.
+/// Rooms are only ever added through a door of an existing room, so the cave is always connected.
+/// Each room then gets a random template that has a door wherever a passage leads.
+/// Every locked door comes with an extra key that can be reached from the exit without unlocking anything.
+#[derive(Debug, Clone)]
+pub struct MapGenerator {
+    pub seed: u64,
+    /// Corners of the bounding box rooms have to fit in, both inclusive. It must contain (0, 0).
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+    pub rooms: usize,
+    pub style: CaveStyle,
+    pub locked_doors: usize,
+    /// Keys needed at the exit, not counting the ones for locked doors.
+    pub keys: u32,
+    pub curses: Vec<Item>,
+}
+
+impl MapGenerator {
+    /// The cave of a game with the default settings.
+    pub fn new(seed: u64) -> MapGenerator {
+        MapGenerator::with_settings(seed, &GameSettings::default())
+    }
+
+    /// Sized and filled the way a game's settings ask for.
+    pub fn with_settings(seed: u64, settings: &GameSettings) -> MapGenerator {
+        MapGenerator {
+            seed,
+            min: (-settings.map_radius, -settings.map_radius),
+            max: (settings.map_radius, settings.map_radius),
+            rooms: settings.rooms,
+            style: settings.style.clone(),
+            locked_doors: 4,
+            keys: settings.keys,
+            curses: settings.curses.clone(),
+        }
+    }
+
+    fn in_bounds(&self, room: (i32, i32)) -> bool {
+        room.0 >= self.min.0 && room.0 <= self.max.0 && room.1 >= self.min.1 && room.1 <= self.max.1
+    }
+
+    pub fn generate(&self) -> Map {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut map = Map::default();
+        // Rooms are kept in insertion order, iterating over the map itself wouldn't be reproducible
+        let mut rooms = vec![(0, 0)];
+        let mut passages = HashSet::new();
+        // Same passages in the order they were made, for the same reason
+        let mut passage_list = vec![];
+        let mut direction = 0;
+        let mut attempts = 0;
+        while rooms.len() < self.rooms && attempts < self.rooms * 100 {
+            attempts += 1;
+            let from = match self.style {
+                CaveStyle::Corridors if rng.gen::<f32>() < 0.9 => *rooms.last().unwrap(),
+                _ => rooms[rng.gen_range(0, rooms.len())],
+            };
+            if self.style != CaveStyle::Corridors || rng.gen::<f32>() < 0.3 {
+                direction = rng.gen_range(0, DIRECTIONS.len());
+            }
+            let offset = DIRECTIONS[direction].offset();
+            let room = (from.0 + offset.0, from.1 + offset.1);
+            if !self.in_bounds(room) || map.room(room.0, room.1).is_some() {
+                continue;
+            }
+            map.add_room(room, Room::basic(room));
+            passages.insert(passage(from, room));
+            passage_list.push(passage(from, room));
+            rooms.push(room);
+        }
+
+        if self.style == CaveStyle::Loops {
+            for room in &rooms {
+                for direction in DIRECTIONS.iter() {
+                    let offset = direction.offset();
+                    let neighbour = (room.0 + offset.0, room.1 + offset.1);
+                    if map.room(neighbour.0, neighbour.1).is_some() && rng.gen::<f32>() < 0.15 {
+                        if passages.insert(passage(*room, neighbour)) {
+                            passage_list.push(passage(*room, neighbour));
+                        }
+                    }
+                }
+            }
+        }
+
+        for room in &rooms {
+            let open: Vec<Direction> = DIRECTIONS
+                .iter()
+                .filter(|direction| {
+                    let offset = direction.offset();
+                    passages.contains(&passage(*room, (room.0 + offset.0, room.1 + offset.1)))
+                })
+                .cloned()
+                .collect();
+            let room = map.mut_room(room.0, room.1).unwrap();
+            if room.position != (0, 0) {
+                let templates = ROOM_TEMPLATES.matching(&open);
+                room.room_type = RoomType::from_template(&templates[rng.gen_range(0, templates.len())].name);
+            }
+            for direction in DIRECTIONS.iter() {
+                let door = if open.contains(direction) { Door::Open } else { Door::Closed };
+                room.doors.set(direction, door);
+            }
+        }
+
+        // Only keep a lock if the exit's side still has a room for each lock's key
+        let mut locked = 0;
+        while locked < self.locked_doors && !passage_list.is_empty() {
+            let (from, to) = passage_list.remove(rng.gen_range(0, passage_list.len()));
+            let direction = DIRECTIONS
+                .iter()
+                .find(|direction| direction.offset() == (to.0 - from.0, to.1 - from.1))
+                .unwrap();
+            set_passage(&mut map, from, direction, Door::Locked);
+            if map.reachable((0, 0)).len() - 1 > locked {
+                locked += 1;
+            } else {
+                set_passage(&mut map, from, direction, Door::Open);
+            }
+        }
+        debug_assert!(map.is_connected());
+
+        let region = map.reachable((0, 0));
+        let mut free_rooms: Vec<(i32, i32)> = rooms[1..].to_vec();
+        for index in 0..locked + self.curses.len() + self.keys as usize {
+            let candidates: Vec<usize> = (0..free_rooms.len())
+                .filter(|candidate| index >= locked || region.contains(&free_rooms[*candidate]))
+                .collect();
+            if candidates.is_empty() {
+                break;
+            }
+            let item = match index.checked_sub(locked) {
+                Some(curse) if curse < self.curses.len() => self.curses[curse].clone(),
+                _ => Item::Key,
+            };
+            let position = free_rooms.remove(candidates[rng.gen_range(0, candidates.len())]);
+            let room = map.mut_room(position.0, position.1).unwrap();
+            let floor = ROOM_TEMPLATES.get(room.room_type.template()).floor_tiles();
+            room.item = Some((item, floor[rng.gen_range(0, floor.len())]));
+        }
+        map
+    }
+}
+
+fn set_passage(map: &mut Map, position: (i32, i32), direction: &Direction, door: Door) {
+    let offset = direction.offset();
+    map.mut_room(position.0, position.1).unwrap().doors.set(direction, door);
+    map.mut_room(position.0 + offset.0, position.1 + offset.1)
+        .unwrap()
+        .doors
+        .set(&direction.opposite(), door);
+}
+
+fn passage(a: (i32, i32), b: (i32, i32)) -> ((i32, i32), (i32, i32)) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every room of `map` sorted by position, `Map` itself is keyed by `HashMap`s.
+    fn layout(map: &Map) -> Vec<String> {
+        let mut rooms: Vec<&Room> = map.all_rooms();
+        rooms.sort_by_key(|room| room.position);
+        rooms.iter().map(|room| format!("{:?}", room)).collect()
+    }
+
+    #[test]
+    fn same_seed_and_settings_give_the_same_map() {
+        let settings = GameSettings::default();
+        for seed in 0..5 {
+            let first = MapGenerator::with_settings(seed, &settings).generate();
+            let second = MapGenerator::with_settings(seed, &settings).generate();
+            assert_eq!(layout(&first), layout(&second));
+        }
+        let first = MapGenerator::with_settings(0, &settings).generate();
+        let other = MapGenerator::with_settings(1, &settings).generate();
+        assert_ne!(layout(&first), layout(&other));
+    }
+
+    #[test]
+    fn caves_are_connected() {
+        for seed in 0..50 {
+            let map = MapGenerator::new(seed).generate();
+            assert!(map.is_connected(), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn every_style_is_connected() {
+        for style in [CaveStyle::Tree, CaveStyle::Loops, CaveStyle::Corridors].iter() {
+            let settings = GameSettings { style: style.clone(), ..GameSettings::default() };
+            for seed in 0..20 {
+                let generator = MapGenerator::with_settings(seed, &settings);
+                assert_eq!(&generator.style, style);
+                let map = generator.generate();
+                assert!(map.is_connected(), "{:?} seed {}", style, seed);
+            }
+        }
+    }
+}
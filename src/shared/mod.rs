@@ -1,4 +1,8 @@
+pub mod effects;
+pub mod game_settings;
 pub mod gamestate;
 pub mod http;
+pub mod map_generator;
 pub mod room;
+pub mod room_template;
 pub mod utils;
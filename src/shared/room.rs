@@ -1,38 +1,95 @@
-use crate::shared::utils::{Direction, ROOM_SIZE};
+use crate::shared::room_template::ROOM_TEMPLATES;
+use crate::shared::utils::Direction;
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::fmt::Debug;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TileType {
     None,
     Floor,
     Exit,
     Wall(WallType),
     Door(Direction),
+    Pit,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Room {
-    pub doors: HashMap<String, bool>,
+    pub doors: Doors,
     pub position: (i32, i32),
     pub room_type: RoomType,
     pub item: Option<(Item, (u32, u32))>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Door {
+    #[serde(rename = "o")]
+    Open,
+    #[serde(rename = "c")]
+    Closed,
+    /// Stays shut until someone opens it with a key.
+    #[serde(rename = "l")]
+    Locked,
+    /// Can only be walked through towards its direction, leaving the room.
+    #[serde(rename = "w")]
+    OneWay,
+}
+
+impl Door {
+    pub fn is_passable(&self) -> bool {
+        match self {
+            Door::Open | Door::OneWay => true,
+            Door::Closed | Door::Locked => false,
+        }
+    }
+}
+
+/// The door on each side of a room, in `DIRECTIONS` order.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Doors([Door; 4]);
+
+impl Doors {
+    pub fn new(door: Door) -> Doors {
+        Doors([door; 4])
+    }
+
+    fn index(direction: &Direction) -> usize {
+        match direction {
+            Direction::Down => 0,
+            Direction::Up => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        }
+    }
+
+    pub fn get(&self, direction: &Direction) -> Door {
+        self.0[Doors::index(direction)]
+    }
+
+    pub fn set(&mut self, direction: &Direction, door: Door) {
+        self.0[Doors::index(direction)] = door;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum RoomType {
     Basic,
     Exit,
+    /// Spawned by the traitor, only the traitor can tell it apart from the real exit.
+    FakeExit,
+    /// Any other layout from the room template registry, by name.
+    Template(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Item {
     Key,
     Clear,
     Spin,
     Bad,
+    Reverse,
+    Teleport,
 }
 
 impl Item {
@@ -42,38 +99,39 @@ impl Item {
             Item::Clear => "clear".to_string(),
             Item::Spin => "spin".to_string(),
             Item::Bad => "bad".to_string(),
+            Item::Reverse => "reverse".to_string(),
+            Item::Teleport => "teleport".to_string(),
         }
     }
 }
 
 impl RoomType {
-    pub fn tile(&self, position: (i32, i32)) -> Tile {
+    pub fn from_template(name: &str) -> RoomType {
+        match name {
+            "basic" => RoomType::Basic,
+            "exit" => RoomType::Exit,
+            name => RoomType::Template(name.to_string()),
+        }
+    }
+
+    pub fn template(&self) -> &str {
         match self {
-            RoomType::Basic => Tile {
-                x: position.0,
-                y: position.1,
-                tile_type: BASIC_ROOM
-                    .get(15 - position.1 as usize)
-                    .unwrap()
-                    .get(position.0 as usize)
-                    .unwrap()
-                    .clone(),
-            },
-            RoomType::Exit => Tile {
-                x: position.0,
-                y: position.1,
-                tile_type: EXIT_ROOM
-                    .get(15 - position.1 as usize)
-                    .unwrap()
-                    .get(position.0 as usize)
-                    .unwrap()
-                    .clone(),
-            },
+            RoomType::Basic => "basic",
+            RoomType::Exit | RoomType::FakeExit => "exit",
+            RoomType::Template(name) => name,
+        }
+    }
+
+    pub fn tile(&self, position: (i32, i32)) -> Tile {
+        Tile {
+            x: position.0,
+            y: position.1,
+            tile_type: ROOM_TEMPLATES.get(self.template()).tile(position),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum WallType {
     North,
     South,
@@ -97,13 +155,8 @@ pub struct Tile {
 
 impl Room {
     pub fn new(position: (i32, i32), room_type: RoomType) -> Self {
-        let mut doors = HashMap::new();
-        doors.insert(Direction::Right.to_string(), true);
-        doors.insert(Direction::Up.to_string(), true);
-        doors.insert(Direction::Down.to_string(), true);
-        doors.insert(Direction::Left.to_string(), true);
         Room {
-            doors,
+            doors: Doors::new(Door::Open),
             position,
             room_type,
             item: None,
@@ -114,25 +167,53 @@ impl Room {
         Room::new(position, RoomType::Exit)
     }
 
+    pub fn is_fake_exit(&self) -> bool {
+        match self.room_type {
+            RoomType::FakeExit => true,
+            _ => false,
+        }
+    }
+
     pub fn basic(position: (i32, i32)) -> Self {
         Room::new(position, RoomType::Basic)
     }
 
+    /// The room as players see it, every item on the floor looks like a key until it's picked up.
+    pub fn disguised(&self) -> Room {
+        let mut room = self.clone();
+        if let Some((_, tile)) = room.item {
+            room.item = Some((Item::Key, tile));
+        }
+        room
+    }
+
     pub fn tile(&self, tile: (i32, i32)) -> Tile {
         self.room_type.tile(tile)
     }
 
     pub fn is_wall(&self, position: (i32, i32)) -> bool {
         match self.tile(position).tile_type {
-            TileType::Wall(_) => true,
-            TileType::Door(direction) => !self.doors[&direction.to_string()],
+            TileType::Wall(_) | TileType::Pit => true,
+            TileType::Door(direction) => !self.doors.get(&direction).is_passable(),
             _ => false,
         }
     }
 
+    /// Whether moving by `movement` onto the tile at `position` runs into something,
+    /// which includes going the wrong way through a one-way door.
+    pub fn blocks(&self, position: (i32, i32), movement: (f32, f32)) -> bool {
+        match self.tile(position).tile_type {
+            TileType::Door(direction) if self.doors.get(&direction) == Door::OneWay => {
+                let offset = direction.offset();
+                (movement.0 * offset.0 as f32 + movement.1 * offset.1 as f32) < 0.
+            }
+            _ => self.is_wall(position),
+        }
+    }
+
     pub fn is_door(&self, position: (i32, i32)) -> bool {
         match self.tile(position).tile_type {
-            TileType::Door(direction) => self.doors[&direction.to_string()],
+            TileType::Door(direction) => self.doors.get(&direction).is_passable(),
             _ => false,
         }
     }
@@ -151,584 +232,18 @@ impl Default for Room {
     }
 }
 
-const BASIC_ROOM: [[TileType; ROOM_SIZE]; ROOM_SIZE] = [
-    [
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Wall(WallType::East),
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::InnerSouthWest),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::OuterSouthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterSouthEast),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::InnerSouthEast),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::OuterSouthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterSouthEast),
-        TileType::Wall(WallType::South),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::OuterNorthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterNorthEast),
-        TileType::Wall(WallType::North),
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::InnerNorthWest),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::OuterNorthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterNorthEast),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::InnerNorthEast),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Wall(WallType::East),
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-    ],
-];
-
-const EXIT_ROOM: [[TileType; ROOM_SIZE]; ROOM_SIZE] = [
-    [
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Door(Direction::Up),
-        TileType::Wall(WallType::East),
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::InnerSouthWest),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::OuterSouthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterSouthEast),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::InnerSouthEast),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::Wall(WallType::South),
-        TileType::Wall(WallType::OuterSouthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterSouthEast),
-        TileType::Wall(WallType::South),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Exit,
-        TileType::Exit,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Exit,
-        TileType::Exit,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Door(Direction::Left),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Door(Direction::Right),
-    ],
-    [
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::OuterNorthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterNorthEast),
-        TileType::Wall(WallType::North),
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::East),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::Wall(WallType::InnerNorthWest),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::OuterNorthWest),
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Floor,
-        TileType::Wall(WallType::OuterNorthEast),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::North),
-        TileType::Wall(WallType::InnerNorthEast),
-        TileType::None,
-    ],
-    [
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::Wall(WallType::West),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Door(Direction::Down),
-        TileType::Wall(WallType::East),
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-        TileType::None,
-    ],
-];
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curses_look_like_keys() {
+        for item in [Item::Key, Item::Clear, Item::Spin, Item::Bad, Item::Reverse, Item::Teleport].iter() {
+            let mut room = Room::basic((2, 3));
+            room.item = Some((item.clone(), (4, 5)));
+            assert_eq!(room.disguised().item, Some((Item::Key, (4, 5))));
+            assert_eq!(room.item, Some((item.clone(), (4, 5))));
+        }
+        assert_eq!(Room::basic((2, 3)).disguised().item, None);
+    }
+}
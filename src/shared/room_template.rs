@@ -0,0 +1,189 @@
+use crate::shared::room::TileType;
+use crate::shared::utils::{Direction, ROOM_SIZE};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+
+/// Environment variable naming a directory of extra templates, loaded on top of the built-in ones.
+pub const ROOM_TEMPLATE_DIRECTORY_VARIABLE: &str = "LYCAN_ROOMS";
+
+/// The templates in `resources/rooms`, built into the game so it runs from any directory.
+const BUILT_IN_TEMPLATES: [(&str, &str); 7] = [
+    ("basic", include_str!("resources/rooms/basic.json")),
+    ("corridor_horizontal", include_str!("resources/rooms/corridor_horizontal.json")),
+    ("corridor_vertical", include_str!("resources/rooms/corridor_vertical.json")),
+    ("exit", include_str!("resources/rooms/exit.json")),
+    ("l_shape", include_str!("resources/rooms/l_shape.json")),
+    ("pillars", include_str!("resources/rooms/pillars.json")),
+    ("pits", include_str!("resources/rooms/pits.json")),
+];
+
+lazy_static! {
+    /// Every template, loaded the first time a room is looked at.
+    pub static ref ROOM_TEMPLATES: RoomTemplates = match RoomTemplates::load_all() {
+        Ok(templates) => templates,
+        Err(err) => panic!("could not load the room templates: {}", err),
+    };
+}
+
+/// A room layout as written in a template file: a `ROOM_SIZE` square grid of characters and a
+/// legend giving the tile each character stands for. The first line of the grid is the row at
+/// y = 15, on the side the `Up` doors are. Doors have to sit in the middle four tiles of their
+/// side, like in the basic template, so they line up with the neighbouring room's.
+#[derive(Debug, Deserialize)]
+struct RoomTemplateFile {
+    legend: HashMap<char, TileType>,
+    grid: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomTemplate {
+    pub name: String,
+    tiles: Vec<Vec<TileType>>,
+}
+
+impl RoomTemplate {
+    pub fn parse(name: &str, source: &str) -> Result<RoomTemplate, Box<dyn Error>> {
+        let file: RoomTemplateFile = serde_json::from_str(source)?;
+        if file.grid.len() != ROOM_SIZE {
+            return Err(format!("room template {} has {} rows instead of {}", name, file.grid.len(), ROOM_SIZE).into());
+        }
+        let mut tiles = Vec::new();
+        for (index, line) in file.grid.iter().enumerate() {
+            let row = line
+                .chars()
+                .map(|character| match file.legend.get(&character) {
+                    Some(tile) => Ok(tile.clone()),
+                    None => Err(format!("room template {} uses {:?} which is not in its legend", name, character)),
+                })
+                .collect::<Result<Vec<TileType>, String>>()?;
+            if row.len() != ROOM_SIZE {
+                return Err(format!("room template {} row {} has {} tiles instead of {}", name, index, row.len(), ROOM_SIZE).into());
+            }
+            tiles.push(row);
+        }
+        Ok(RoomTemplate {
+            name: name.to_string(),
+            tiles,
+        })
+    }
+
+    pub fn has_door(&self, direction: &Direction) -> bool {
+        self.tiles.iter().flatten().any(|tile| match tile {
+            TileType::Door(door) => door == direction,
+            _ => false,
+        })
+    }
+
+    /// Tiles players can stand on, row by row so the order is always the same.
+    pub fn floor_tiles(&self) -> Vec<(u32, u32)> {
+        let mut floor = vec![];
+        for y in 0..ROOM_SIZE as u32 {
+            for x in 0..ROOM_SIZE as u32 {
+                if let TileType::Floor = self.tile((x as i32, y as i32)) {
+                    floor.push((x, y));
+                }
+            }
+        }
+        floor
+    }
+
+    pub fn tile(&self, position: (i32, i32)) -> TileType {
+        if position.0 < 0 || position.1 < 0 || position.0 >= ROOM_SIZE as i32 || position.1 >= ROOM_SIZE as i32 {
+            return TileType::None;
+        }
+        self.tiles[ROOM_SIZE - 1 - position.1 as usize][position.0 as usize].clone()
+    }
+}
+
+/// Room templates keyed by name, the name being the template's file name without its extension.
+#[derive(Debug)]
+pub struct RoomTemplates {
+    templates: HashMap<String, RoomTemplate>,
+}
+
+impl RoomTemplates {
+    /// The built-in templates, with the ones in the `ROOM_TEMPLATE_DIRECTORY_VARIABLE` directory
+    /// added to them. Those replace the built-in templates with the same name.
+    pub fn load_all() -> Result<RoomTemplates, Box<dyn Error>> {
+        let mut templates = HashMap::new();
+        for (name, source) in BUILT_IN_TEMPLATES.iter() {
+            templates.insert(name.to_string(), RoomTemplate::parse(name, source)?);
+        }
+        if let Ok(directory) = env::var(ROOM_TEMPLATE_DIRECTORY_VARIABLE) {
+            templates.extend(RoomTemplates::read_directory(&directory)?);
+        }
+        Ok(RoomTemplates { templates })
+    }
+
+    /// Only the templates in `directory`, which has to hold at least the basic and exit ones.
+    pub fn load(directory: &str) -> Result<RoomTemplates, Box<dyn Error>> {
+        let templates = RoomTemplates::read_directory(directory)?;
+        for required in &["basic", "exit"] {
+            if !templates.contains_key(*required) {
+                return Err(format!("missing the {} room template in {}", required, directory).into());
+            }
+        }
+        Ok(RoomTemplates { templates })
+    }
+
+    fn read_directory(directory: &str) -> Result<HashMap<String, RoomTemplate>, Box<dyn Error>> {
+        let mut templates = HashMap::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |extension| extension != "json") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let template = RoomTemplate::parse(&name, &fs::read_to_string(&path)?)?;
+            templates.insert(name, template);
+        }
+        Ok(templates)
+    }
+
+    /// Falls back to the basic template for names we don't know about.
+    pub fn get(&self, name: &str) -> &RoomTemplate {
+        match self.templates.get(name) {
+            Some(template) => template,
+            None => &self.templates["basic"],
+        }
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        self.templates.keys().collect()
+    }
+
+    /// Templates other than the exit with a door on every side in `doors`, sorted by name.
+    pub fn matching(&self, doors: &[Direction]) -> Vec<&RoomTemplate> {
+        let mut templates: Vec<&RoomTemplate> = self
+            .templates
+            .values()
+            .filter(|template| template.name != "exit")
+            .filter(|template| doors.iter().all(|direction| template.has_door(direction)))
+            .collect();
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        templates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_template_file_is_built_in() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shared/resources/rooms");
+        let mut files: Vec<String> = RoomTemplates::load(directory).unwrap().names().into_iter().cloned().collect();
+        files.sort();
+        let built_in: Vec<String> = BUILT_IN_TEMPLATES.iter().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(files, built_in);
+        assert!(RoomTemplates::load_all().is_ok());
+    }
+}
@@ -2,8 +2,16 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 pub const ROOM_SIZE: usize = 16;
+pub const TILE_SIZE: f32 = 16.;
+pub const PLAYER_SPEED: f32 = 3.;
+pub const UPDATE_INTERVAL: u64 = 15;
+pub const KEYS_REQUIRED: u32 = 8;
+/// Exits the explorers have to find to win the match.
+pub const ROUND_TARGET: u32 = 3;
+/// How close players have to be to hand each other a key.
+pub const GIVE_DISTANCE: f32 = 48.;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Direction {
     Down,
     Up,
@@ -11,7 +19,28 @@ pub enum Direction {
     Right,
 }
 
+pub const DIRECTIONS: [Direction; 4] = [Direction::Down, Direction::Up, Direction::Left, Direction::Right];
+
 impl Direction {
+    /// Room coordinate offset of the neighbour behind a door in this direction.
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Direction::Down => (0, -1),
+            Direction::Up => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::Down => Direction::Up,
+            Direction::Up => Direction::Down,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             Direction::Down => String::from("Down"),
@@ -21,3 +50,30 @@ impl Direction {
         }
     }
 }
+
+pub fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+pub fn room_coord(position: (f32, f32)) -> (i32, i32) {
+    let room_x = position.0 / TILE_SIZE / ROOM_SIZE as f32;
+    let room_y = position.1 / TILE_SIZE / ROOM_SIZE as f32;
+    (room_x.floor() as i32, room_y.floor() as i32)
+}
+
+/// Position of the middle of `tile` in the room at `room`, the opposite of `room_coord` and `tile_coord`.
+pub fn tile_center(room: (i32, i32), tile: (u32, u32)) -> (f32, f32) {
+    let room_pixels = TILE_SIZE * ROOM_SIZE as f32;
+    (
+        room.0 as f32 * room_pixels + tile.0 as f32 * TILE_SIZE + TILE_SIZE / 2.,
+        room.1 as f32 * room_pixels + tile.1 as f32 * TILE_SIZE + TILE_SIZE / 2.,
+    )
+}
+
+pub fn tile_coord(position: (f32, f32)) -> (i32, i32) {
+    let room_pixels = (TILE_SIZE as i32) * ROOM_SIZE as i32;
+    (
+        (((position.0 as i32).rem_euclid(room_pixels)) as f32 / TILE_SIZE).floor() as i32,
+        (((position.1 as i32).rem_euclid(room_pixels)) as f32 / TILE_SIZE).floor() as i32,
+    )
+}